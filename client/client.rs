@@ -10,8 +10,8 @@ use bytes::{BufMut, BytesMut};
 use tokio_io::codec::{Encoder, Decoder};
 
 use version::PKG_INFO;
-use event::Reason;
-use master_types::{MasterRequest, MasterResponse};
+use event::{LogLine, OutputStream, ProcessStats, Reason};
+use master_types::{CanaryRequest, DeployRequest, LogsRequest, MasterRequest, MasterResponse};
 
 /// Console commands
 #[derive(Clone, Debug)]
@@ -20,12 +20,22 @@ pub enum ClientCommand {
     Pause(String),
     Resume(String),
     Reload(String),
+    /// Service name, number of canary workers, observation period
+    CanaryReload(String, usize, u64),
+    /// Service name, settings, number of canary workers, observation period
+    Deploy(String, Vec<(String, String)>, Option<usize>, u64),
+    Rollback(String),
+    Scale(String, u16),
     Restart(String),
     Stop(String),
     Status(String),
     SPid(String),
+    /// Service name, worker number, number of lines, follow output
+    Logs(String, Option<usize>, usize, bool),
     Pid,
     Quit,
+    Reread,
+    Update,
     Version,
     VersionCheck,
 }
@@ -43,6 +53,10 @@ pub fn read_response(stream: &mut UnixStream, buf: &mut BytesMut)
                      -> Result<MasterResponse, io::Error>
 {
     loop {
+        // several responses could be received at once
+        if let Some(resp) = ClientTransportCodec.decode(buf)? {
+            return Ok(resp)
+        }
         buf.reserve(1024);
 
         unsafe {
@@ -125,6 +139,14 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
             send_command(&mut stream, MasterRequest::Status(name)),
         ClientCommand::SPid(name) =>
             send_command(&mut stream, MasterRequest::SPid(name)),
+        ClientCommand::Logs(name, worker, lines, follow) => {
+            let req = LogsRequest { name, worker, lines };
+            if follow {
+                send_command(&mut stream, MasterRequest::Tail(req))
+            } else {
+                send_command(&mut stream, MasterRequest::Logs(req))
+            }
+        }
         ClientCommand::Pause(name) => {
             println!("Pause `{}` service.", name);
             send_command(&mut stream, MasterRequest::Pause(name))
@@ -141,6 +163,25 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
             print!("Reloading `{}` service.", name);
             send_command(&mut stream, MasterRequest::Reload(name))
         }
+        ClientCommand::CanaryReload(name, workers, observe) => {
+            print!("Reloading `{}` service, observing {} canary workers for {}s.",
+                   name, workers, observe);
+            send_command(&mut stream, MasterRequest::CanaryReload(
+                CanaryRequest { name, workers, observe }))
+        }
+        ClientCommand::Deploy(name, settings, canary, observe) => {
+            print!("Deploying `{}` service.", name);
+            send_command(&mut stream, MasterRequest::Deploy(
+                DeployRequest { name, settings, canary, observe }))
+        }
+        ClientCommand::Rollback(name) => {
+            print!("Rolling back `{}` service.", name);
+            send_command(&mut stream, MasterRequest::Rollback(name))
+        }
+        ClientCommand::Scale(name, num) => {
+            print!("Scaling `{}` service to {} workers.", name, num);
+            send_command(&mut stream, MasterRequest::Scale(name, num))
+        }
         ClientCommand::Restart(name) => {
             print!("Restarting `{}` service", name);
             send_command(&mut stream, MasterRequest::Restart(name))
@@ -159,6 +200,12 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
             print!("Quiting.");
             send_command(&mut stream, MasterRequest::Quit)
         }
+        ClientCommand::Reread => {
+            send_command(&mut stream, MasterRequest::Reread)
+        }
+        ClientCommand::Update => {
+            send_command(&mut stream, MasterRequest::Update)
+        }
     };
     let _ = io::stdout().flush();
 
@@ -171,11 +218,23 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
     loop {
         match try_read_response(&mut stream, &mut buf) {
             Ok(MasterResponse::Pong) => {
+                if let ClientCommand::Logs(..) = cmd {
+                    continue
+                }
                 print!(".");
                 let _ = io::stdout().flush();
             }
+            Ok(MasterResponse::LogLines(lines)) => {
+                for line in lines {
+                    print_line(&line);
+                }
+            }
             Ok(MasterResponse::Done) => {
-                println!();
+                match cmd {
+                    ClientCommand::Logs(..) => (),
+                    ClientCommand::Scale(..) => println!("done"),
+                    _ => println!(),
+                }
                 return true
             }
             Ok(MasterResponse::Pid(pid)) => {
@@ -197,8 +256,12 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
                 return true
             }
             Ok(MasterResponse::ServiceStatus(status)) => {
-                println!("Service status: {}", status.0);
-                for worker in status.1 {
+                println!("Service status: {}", status.status);
+                // revisions are shown after first deploy
+                let revised = status.revisions.last().is_some_and(|rev| rev.id > 1) ||
+                    status.workers.iter().flat_map(|worker| worker.1.iter())
+                    .any(|ev| ev.revision.is_some_and(|rev| rev > 1));
+                for worker in status.workers {
                     for ev in worker.1 {
                         let dt = Local.timestamp(ev.timestamp as i64, 0);
                         print!("{} {}: ", worker.0, dt.format("%Y-%m-%d %H:%M:%S"));
@@ -210,11 +273,95 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
                             Reason::None | Reason::Initial => (),
                             _ => print!(", reason: {:?}", ev.reason),
                         }
+                        match ev.revision {
+                            Some(revision) if revised => print!(", revision: {}", revision),
+                            _ => (),
+                        }
+                        if let Some(ref backoff) = ev.backoff {
+                            let retry = Local.timestamp(backoff.retry_at as i64, 0);
+                            print!(", retry in {:.1}s at {}",
+                                   backoff.delay as f64 / 1000.0, retry.format("%H:%M:%S"));
+                        }
                         println!();
+                        if let Some(stderr) = ev.stderr {
+                            for line in stderr {
+                                println!("    {}", line);
+                            }
+                        }
+                    }
+                }
+                if !status.stats.is_empty() {
+                    println!("{:<8} {:>8} {:>6} {:>8} {:>7} {:>6} {:>8} {:>8} {:>8} {:>8} {:>9}",
+                             "WORKER", "PID", "CPU%", "RSS", "THREADS", "FDS",
+                             "READ", "WRITE", "UPTIME", "RESTARTS", "LOAD");
+                    for stats in &status.stats {
+                        print_stats(&format!("{}", stats.worker), stats);
+                    }
+                    if let Some(ref total) = status.total {
+                        print_stats("total", total);
+                    }
+                }
+                if let Some(usage) = status.usage {
+                    print!("Memory: {}", format_size(usage.memory));
+                    if let Some(max) = usage.memory_max {
+                        print!(" of {}", format_size(max));
+                    }
+                    println!(", cpu: {:.2}s, processes: {}, oom kills: {}",
+                             usage.cpu_usec as f64 / 1_000_000.0, usage.pids, usage.oom_kills);
+                }
+                if !status.environ.is_empty() {
+                    println!("Environment:");
+                    for (key, val) in status.environ {
+                        println!("  {}={}", key, val);
+                    }
+                }
+                if revised {
+                    println!("Revisions:");
+                    let current = status.revisions.len() - 1;
+                    for (idx, rev) in status.revisions.iter().enumerate() {
+                        let dt = Local.timestamp(rev.timestamp as i64, 0);
+                        println!("  {} {}: {}{}", rev.id, dt.format("%Y-%m-%d %H:%M:%S"),
+                                 rev.description, if idx == current { " (current)" } else { "" });
+                    }
+                }
+                if !status.scaling.is_empty() {
+                    println!("Scaling:");
+                    for ev in &status.scaling {
+                        let dt = Local.timestamp(ev.timestamp as i64, 0);
+                        println!("  {}: {} -> {} workers, reason: {:?}",
+                                 dt.format("%Y-%m-%d %H:%M:%S"), ev.from, ev.to, ev.reason);
                     }
                 }
                 return true
             }
+            Ok(MasterResponse::ConfigChanges(changes)) => {
+                if changes.is_empty() {
+                    println!("No changes.");
+                }
+                let lists = [
+                    ("service added", &changes.services_added),
+                    ("service removed", &changes.services_removed),
+                    ("service changed", &changes.services_changed),
+                    ("socket added", &changes.sockets_added),
+                    ("socket removed", &changes.sockets_removed),
+                    ("socket changed", &changes.sockets_changed)];
+                for item in &lists {
+                    for name in item.1 {
+                        println!("{}: {}", name, item.0);
+                    }
+                }
+                for err in &changes.errors {
+                    error!("{}", err);
+                }
+                if changes.applied && !changes.is_empty() {
+                    println!("Configuration updated.");
+                }
+                return changes.errors.is_empty()
+            }
+            Ok(MasterResponse::ErrorConfig(err)) => {
+                error!("Configuration error: {}", err);
+                return false
+            }
             Ok(MasterResponse::ServiceWorkerPids(pids)) => {
                 for pid in pids {
                     println!("{}", pid);
@@ -225,6 +372,10 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
                 println!("failed.");
                 return false
             },
+            Ok(MasterResponse::ServiceReloadFailed(reason)) => {
+                println!("failed, reason: {:?}", reason);
+                return false
+            },
             Ok(MasterResponse::ErrorNotReady) => {
                 error!("Service is loading");
                 return false
@@ -233,6 +384,10 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
                 error!("Service is unknown");
                 return false
             }
+            Ok(MasterResponse::ErrorUnknownWorker) => {
+                error!("Worker is unknown");
+                return false
+            }
             Ok(MasterResponse::ErrorServiceStarting) => {
                 error!("Service is starting");
                 return false
@@ -241,6 +396,14 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
                 error!("Service is restarting");
                 return false
             }
+            Ok(MasterResponse::ErrorNoRevision) => {
+                error!("Service has no previous revision");
+                return false
+            }
+            Ok(MasterResponse::ErrorServiceScaling) => {
+                error!("Removed workers are still stopping");
+                return false
+            }
             Ok(MasterResponse::ErrorServiceStopping) => {
                 error!("Service is stopping");
                 return false
@@ -255,6 +418,51 @@ pub fn run(cmd: ClientCommand, sock: &str) -> bool {
     }
 }
 
+fn print_line(line: &LogLine) {
+    let dt = Local.timestamp(line.timestamp as i64, 0);
+    let msg = format!("{} worker({}) (pid:{}) {}",
+                      dt.format("%Y-%m-%d %H:%M:%S"), line.worker, line.pid, line.line);
+    match line.stream {
+        OutputStream::Stdout => println!("{}", msg),
+        OutputStream::Stderr => eprintln!("{}", msg),
+    }
+}
+
+fn print_stats(name: &str, stats: &ProcessStats) {
+    let uptime = if stats.pid.is_empty() { String::new() } else { format_duration(stats.uptime) };
+    // requests in process and waiting requests
+    let load = match stats.load {
+        Some(load) => format!("{}/{}", load.inflight, load.queue),
+        None => "-".to_owned(),
+    };
+    println!("{:<8} {:>8} {:>6.1} {:>8} {:>7} {:>6} {:>8} {:>8} {:>8} {:>8} {:>9}",
+             name, stats.pid, stats.cpu, format_size(stats.rss), stats.threads, stats.fds,
+             format_size(stats.read_bytes), format_size(stats.write_bytes),
+             uptime, stats.restarts, load);
+}
+
+fn format_duration(secs: u64) -> String {
+    if secs >= 86_400 {
+        format!("{}d {}h", secs / 86_400, secs % 86_400 / 3600)
+    } else if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+fn format_size(size: u64) -> String {
+    if size >= 1 << 30 {
+        format!("{:.1}G", size as f64 / f64::from(1 << 30))
+    } else if size >= 1 << 20 {
+        format!("{:.1}M", size as f64 / f64::from(1 << 20))
+    } else {
+        format!("{}K", size >> 10)
+    }
+}
+
 pub struct ClientTransportCodec;
 
 impl Encoder for ClientTransportCodec
@@ -1,5 +1,6 @@
 use structopt::StructOpt;
 use client::ClientCommand;
+use duration::parse_duration;
 
 
 #[derive(StructOpt, Debug)]
@@ -8,11 +9,43 @@ struct Cli {
     #[structopt(long="sock", short="m", default_value="fectld.sock")]
     sock: String,
 
-    /// Run command (Supported commands: status, start, reload, restart, stop)
+    /// Run command (Supported commands: status, start, reload, restart, stop, reread, update,
+    /// logs, tail, deploy, rollback, scale)
     command: String,
 
     /// Service name
     name: Option<String>,
+
+    /// Number of workers, `scale` command
+    num: Option<u16>,
+
+    /// Show output of this worker only, `logs` and `tail` commands
+    #[structopt(long="worker", short="w")]
+    worker: Option<usize>,
+
+    /// Number of recent output lines, `logs` and `tail` commands
+    #[structopt(long="lines", short="n")]
+    lines: Option<usize>,
+
+    /// Keep printing new output lines, `logs` and `tail` commands
+    #[structopt(long="follow", short="f")]
+    follow: bool,
+
+    /// Worker command of new revision, `deploy` command
+    #[structopt(long="command")]
+    worker_command: Option<String>,
+
+    /// Configuration setting of new revision, i.e. "env_file=prod.env", `deploy` command
+    #[structopt(long="set")]
+    set: Vec<String>,
+
+    /// Reload this many workers first and observe them, `reload` and `deploy` commands
+    #[structopt(long="canary")]
+    canary: Option<usize>,
+
+    /// Canary observation period, i.e. "120s" or "2m", default 60 seconds
+    #[structopt(long="observe")]
+    observe: Option<String>,
 }
 
 
@@ -28,6 +61,10 @@ pub fn load_config() -> Option<(ClientCommand, String)> {
             return Some((ClientCommand::Pid, sock)),
         "quit" =>
             return Some((ClientCommand::Quit, sock)),
+        "reread" =>
+            return Some((ClientCommand::Reread, sock)),
+        "update" =>
+            return Some((ClientCommand::Update, sock)),
         "version" =>
             return Some((ClientCommand::Version, sock)),
         "version-check" =>
@@ -49,10 +86,51 @@ pub fn load_config() -> Option<(ClientCommand, String)> {
         "spid" => ClientCommand::SPid(name),
         "start" => ClientCommand::Start(name),
         "stop" => ClientCommand::Stop(name),
-        "reload" => ClientCommand::Reload(name),
+        "reload" => match args.canary {
+            Some(workers) => ClientCommand::CanaryReload(name, workers, observe(&args)?),
+            None => ClientCommand::Reload(name),
+        },
+        "deploy" => {
+            let mut settings = Vec::new();
+            if let Some(ref command) = args.worker_command {
+                settings.push(("command".to_owned(), command.clone()));
+            }
+            for item in &args.set {
+                let mut parts = item.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if !key.trim().is_empty() =>
+                        settings.push((key.trim().to_owned(), value.to_owned())),
+                    _ => {
+                        println!("Expected key=value setting: {}", item);
+                        return None
+                    }
+                }
+            }
+            if settings.is_empty() {
+                println!("`--command` or `--set` is required");
+                return None
+            }
+            ClientCommand::Deploy(name, settings, args.canary, observe(&args)?)
+        }
+        "rollback" => ClientCommand::Rollback(name),
+        "scale" => match args.num {
+            Some(num) if num > 0 => ClientCommand::Scale(name, num),
+            _ => {
+                println!("Number of workers is required");
+                return None
+            }
+        },
         "restart" => ClientCommand::Restart(name),
         "pause" => ClientCommand::Pause(name),
         "resume" => ClientCommand::Resume(name),
+        "logs" | "tail" => {
+            if args.worker == Some(0) {
+                println!("Worker number starts from 1");
+                return None
+            }
+            let lines = args.lines.unwrap_or(if cmd == "tail" { 10 } else { 1000 });
+            ClientCommand::Logs(name, args.worker, lines, args.follow)
+        }
         _ => {
             println!("Unknown command: {}", cmd);
             return None
@@ -60,3 +138,17 @@ pub fn load_config() -> Option<(ClientCommand, String)> {
     };
     return Some((cmd, sock))
 }
+
+/// Canary observation period in seconds, default 60 seconds
+fn observe(args: &Cli) -> Option<u64> {
+    match args.observe {
+        Some(ref observe) => match parse_duration(observe) {
+            Some(observe) => Some(observe),
+            None => {
+                println!("Can not parse observation period: {}", observe);
+                None
+            }
+        },
+        None => Some(60),
+    }
+}
@@ -23,6 +23,9 @@ mod event {
 mod master_types {
     include!("../src/master_types.rs");
 }
+mod duration {
+    include!("../src/duration.rs");
+}
 
 
 fn main() {
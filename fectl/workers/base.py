@@ -84,7 +84,12 @@ class Worker(object):
         raise NotImplementedError()
 
     def heartbeat(self):
-        self.notify(self.MSG_HEARTBEAT)
+        self.notify(self.MSG_HEARTBEAT, self.load())
+
+    def load(self):
+        """ worker load reported with heartbeat, i.e.
+        ``{'inflight': 10, 'queue': 2}``, ``None`` if not available """
+        return None
 
     def on_shutdown(self, cb):
         """ register callback for graceful shutdown process """
@@ -0,0 +1,125 @@
+// cgroup v2 management
+//
+// Each service gets `<root>/<service>` cgroup with service limits,
+// each worker process is moved to `<root>/<service>/worker-<pid>` cgroup.
+use std;
+use std::path::Path;
+use std::io::prelude::*;
+
+use nix::unistd::Pid;
+
+use config::ServiceConfig;
+use event::ResourceUsage;
+use process::ProcessError;
+
+const CONTROLLERS: &str = "+memory +cpu +pids";
+
+
+/// Create service cgroup and apply service limits
+pub fn configure(cfg: &ServiceConfig) -> Result<(), String> {
+    let path = match cfg.cgroup {
+        Some(ref path) => path,
+        None => return Ok(()),
+    };
+
+    // enable controllers for service and worker cgroups
+    if let Some(root) = path.parent() {
+        create(root)?;
+        write(root, "cgroup.subtree_control", CONTROLLERS)?;
+    }
+    create(path)?;
+    write(path, "cgroup.subtree_control", CONTROLLERS)?;
+
+    let limit = |val: Option<u64>| val.map(|v| v.to_string()).unwrap_or_else(|| "max".to_owned());
+    write(path, "memory.max", &limit(cfg.memory_max))?;
+    write(path, "pids.max", &limit(cfg.pids_max))?;
+    write(path, "cpu.weight", &cfg.cpu_weight.unwrap_or(100).to_string())?;
+    write(path, "cpu.max", cfg.cpu_max.as_deref().unwrap_or("max"))?;
+    Ok(())
+}
+
+/// Move worker process to its own cgroup
+pub fn attach(cfg: &ServiceConfig, pid: Pid) -> Result<(), String> {
+    if let Some(ref path) = cfg.cgroup {
+        let path = path.join(format!("worker-{}", pid));
+        create(&path)?;
+        write(&path, "cgroup.procs", &pid.to_string())?;
+    }
+    Ok(())
+}
+
+/// Check if worker process got killed by OOM killer, remove worker cgroup
+pub fn exited(cfg: &ServiceConfig, pid: Pid, err: ProcessError) -> ProcessError {
+    let path = match cfg.cgroup {
+        Some(ref path) => path.join(format!("worker-{}", pid)),
+        None => return err,
+    };
+    if !path.exists() {
+        return err
+    }
+
+    let oom_killed = match err {
+        ProcessError::Signal(9) =>
+            read_keyed(&path, "memory.events", "oom_kill").map(|n| n > 0).unwrap_or(false),
+        _ => false,
+    };
+    if let Err(err) = std::fs::remove_dir(&path) {
+        warn!("Can not remove cgroup {:?}: {}", path, err);
+    }
+
+    if oom_killed {
+        ProcessError::OutOfMemory
+    } else {
+        err
+    }
+}
+
+/// Aggregated resource usage of service workers
+pub fn usage(cfg: &ServiceConfig) -> Option<ResourceUsage> {
+    cfg.cgroup.as_ref().filter(|path| path.is_dir()).map(|path| ResourceUsage {
+        memory: read_value(path, "memory.current").unwrap_or(0),
+        memory_max: cfg.memory_max,
+        cpu_usec: read_keyed(path, "cpu.stat", "usage_usec").unwrap_or(0),
+        pids: read_value(path, "pids.current").unwrap_or(0),
+        oom_kills: read_keyed(path, "memory.events", "oom_kill").unwrap_or(0),
+    })
+}
+
+fn create(path: &Path) -> Result<(), String> {
+    match std::fs::create_dir(path) {
+        Ok(_) => Ok(()),
+        Err(ref err) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(err) => Err(format!("Can not create cgroup {:?}: {}", path, err)),
+    }
+}
+
+fn write(path: &Path, name: &str, value: &str) -> Result<(), String> {
+    std::fs::OpenOptions::new().write(true).open(path.join(name))
+        .and_then(|mut f| f.write_all(value.as_bytes()))
+        .map_err(|err| format!("Can not write {:?} to {:?}: {}", value, path.join(name), err))
+}
+
+fn read(path: &Path, name: &str) -> Option<String> {
+    let mut content = String::new();
+    std::fs::File::open(path.join(name))
+        .and_then(|mut f| f.read_to_string(&mut content)).ok()?;
+    Some(content)
+}
+
+fn read_value(path: &Path, name: &str) -> Option<u64> {
+    read(path, name).and_then(|s| s.trim().parse().ok())
+}
+
+/// Read value from flat keyed file, like `memory.events`
+fn read_keyed(path: &Path, name: &str, key: &str) -> Option<u64> {
+    read(path, name).and_then(|content| {
+        content.lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next()) {
+                    (Some(k), Some(v)) if k == key => v.parse().ok(),
+                    _ => None,
+                }
+            }).next()
+    })
+}
@@ -1,5 +1,5 @@
-use std::rc::Rc;
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use nix::unistd::getpid;
 use nix::sys::wait::{waitpid, WaitStatus, WNOHANG};
@@ -7,11 +7,15 @@ use nix::sys::wait::{waitpid, WaitStatus, WNOHANG};
 use actix::Response;
 use actix::prelude::*;
 use actix::actors::signal;
-use futures::Future;
+use futures::{future, Future, Stream};
+use libc;
+use tokio_signal;
 
-use config::Config;
-use event::{Reason, ServiceStatus};
+use config::{self, Config, ServiceConfig, SocketConfig};
+use event::{LogLine, Reason, ServiceStatus};
+use socket::Socket;
 use process::ProcessError;
+use master_types::ConfigChanges;
 use service::{self, FeService, StartStatus, ReloadStatus, ServiceOperationError};
 
 #[derive(Debug)]
@@ -21,10 +25,14 @@ pub enum CommandError {
     NotReady,
     /// service is not known
     UnknownService,
+    /// worker is not known
+    UnknownWorker,
     /// service is stopped
     ServiceStopped,
     /// underlying service error
     Service(ServiceOperationError),
+    /// configuration file error
+    Config(String),
 }
 
 
@@ -36,22 +44,27 @@ enum State {
 }
 
 pub struct CommandCenter {
-    cfg: Rc<Config>,
+    cfg: Config,
     state: State,
     system: Addr<Syn, System>,
     services: HashMap<String, Addr<Unsync, FeService>>,
+    /// services removed from configuration, waiting for workers shutdown
+    retired: HashMap<usize, Addr<Unsync, FeService>>,
+    retired_id: usize,
     stop_waiter: Option<actix::Condition<bool>>,
     stopping: usize,
 }
 
 impl CommandCenter {
 
-    pub fn start(cfg: Rc<Config>) -> Addr<Unsync, CommandCenter> {
+    pub fn start(cfg: Config) -> Addr<Unsync, CommandCenter> {
         CommandCenter {
             cfg,
             state: State::Starting,
             system: Arbiter::system(),
             services: HashMap::new(),
+            retired: HashMap::new(),
+            retired_id: 0,
             stop_waiter: None,
             stopping: 0,
         }.start()
@@ -93,6 +106,206 @@ impl CommandCenter {
             };
         }
     }
+
+    /// Gracefully stop service and keep it around until all workers exit
+    fn retire(&mut self, service: Addr<Unsync, FeService>, ctx: &mut Context<Self>) {
+        let id = self.retired_id;
+        self.retired_id += 1;
+        self.retired.insert(id, service.clone());
+
+        service.send(service::Stop(true, Reason::ConfigReload))
+            .into_actor(self)
+            .then(move |_, act, _| {
+                act.retired.remove(&id);
+                actix::fut::ok(())
+            }).spawn(ctx);
+    }
+
+    /// Re-read configuration file and compare with running configuration.
+    ///
+    /// If `apply` is true, added sockets and services get started, removed get stopped,
+    /// services with changed configuration or sockets get gracefully reloaded.
+    fn reload_config(&mut self, apply: bool, ctx: &mut Context<Self>)
+                     -> ActorResponse<Self, ConfigChanges, CommandError>
+    {
+        let (sockets, mut services) = match config::reload_config(&self.cfg.master) {
+            Ok(cfg) => cfg,
+            Err(err) => return ActorResponse::reply(Err(CommandError::Config(err))),
+        };
+        for srv in &mut services {
+            srv.sockets = Socket::service_sockets(&self.cfg.sockets, &srv.name);
+        }
+        let mut changes = ConfigChanges::default();
+
+        // sockets
+        for sock in &self.cfg.sockets {
+            match sockets.iter().find(|cfg| cfg.name == sock.name) {
+                None => changes.sockets_removed.push(sock.name.clone()),
+                Some(cfg) => if *cfg != sock.cfg {
+                    changes.sockets_changed.push(sock.name.clone());
+                }
+            }
+        }
+        for sock in &sockets {
+            if !self.cfg.sockets.iter().any(|s| s.name == sock.name) {
+                changes.sockets_added.push(sock.name.clone());
+            }
+        }
+
+        // services, change of `num` only does not require reload
+        let mut reload = Vec::new();
+        {
+            // service has to be reloaded if any of its sockets changed
+            let affected: Vec<&SocketConfig> = self.cfg.sockets.iter().map(|s| &s.cfg)
+                .chain(sockets.iter())
+                .filter(|s| changes.sockets_added.contains(&s.name) ||
+                        changes.sockets_removed.contains(&s.name) ||
+                        changes.sockets_changed.contains(&s.name))
+                .collect();
+            let sockets_changed = |name: &String| affected.iter().any(
+                |s| s.service.is_empty() || s.service.contains(name));
+
+            for srv in &self.cfg.services {
+                match services.iter().find(|cfg| cfg.name == srv.name) {
+                    None => changes.services_removed.push(srv.name.clone()),
+                    Some(cfg) => if cfg != srv || sockets_changed(&srv.name) {
+                        changes.services_changed.push(srv.name.clone());
+                        let num_only = cfg.with_num(srv.num) == *srv;
+                        if !num_only || sockets_changed(&srv.name) {
+                            reload.push(srv.name.clone());
+                        }
+                    }
+                }
+            }
+            for srv in &services {
+                if !self.cfg.services.iter().any(|s| s.name == srv.name) {
+                    changes.services_added.push(srv.name.clone());
+                }
+            }
+        }
+
+        if !apply {
+            return ActorResponse::reply(Ok(changes))
+        }
+        changes.applied = true;
+
+        // close removed and changed sockets, start new listeners
+        {
+            let closed = |name: &String| changes.sockets_removed.contains(name) ||
+                changes.sockets_changed.contains(name);
+            self.cfg.sockets.retain(|s| !closed(&s.name));
+        }
+        for sock in &sockets {
+            if changes.sockets_added.contains(&sock.name) ||
+                changes.sockets_changed.contains(&sock.name)
+            {
+                match Socket::bind(sock) {
+                    Ok(sock) => self.cfg.sockets.push(sock),
+                    Err(err) => {
+                        error!("Can not start listener for `{}` socket: {}", sock.name, err);
+                        changes.errors.push(format!("{}", err));
+                    }
+                }
+            }
+        }
+
+        for srv in &mut services {
+            srv.sockets = Socket::service_sockets(&self.cfg.sockets, &srv.name);
+        }
+
+        // stop removed services
+        for name in &changes.services_removed {
+            if let Some(srv) = self.services.remove(name) {
+                info!("Stopping removed service {:?}", name);
+                self.retire(srv, ctx);
+            }
+        }
+
+        // start new services, update changed services
+        let mut resizes = Vec::new();
+        for cfg in &mut services {
+            if changes.services_added.contains(&cfg.name) {
+                info!("Starting new service {:?}", cfg.name);
+                self.services.insert(
+                    cfg.name.clone(), FeService::start(cfg.num, cfg.clone()));
+            }
+            else if changes.services_changed.contains(&cfg.name) {
+                let num = self.cfg.services.iter()
+                    .find(|srv| srv.name == cfg.name).map(|srv| srv.num);
+
+                if let Some(srv) = self.services.get(&cfg.name) {
+                    let resize = num.filter(|num| *num != cfg.num);
+                    if resize.is_some() {
+                        info!("Resizing service {:?} to {} workers", cfg.name, cfg.num);
+                        resizes.push((cfg.name.clone(), cfg.num,
+                                      srv.send(service::Scale(cfg.num as usize))));
+                    }
+                    if reload.contains(&cfg.name) {
+                        info!("Reloading service {:?} with new configuration", cfg.name);
+                        self.update_service(srv, cfg.clone(), ctx);
+                    }
+                    // new `num` is remembered after service is resized
+                    if let Some(num) = resize {
+                        *cfg = cfg.with_num(num);
+                    }
+                }
+            }
+        }
+        self.cfg.services = services;
+
+        if resizes.is_empty() {
+            return ActorResponse::reply(Ok(changes))
+        }
+        let resizes = resizes.into_iter()
+            .map(|(name, num, fut)| fut.then(move |res| Ok::<_, ()>((name, num, res))));
+        ActorResponse::async(
+            future::join_all(resizes)
+                .into_actor(self)
+                .then(move |res, act, _| {
+                    for (name, num, res) in res.unwrap_or_default() {
+                        match res {
+                            Ok(Ok(())) => {
+                                if let Some(cfg) = act.cfg.services.iter_mut()
+                                    .find(|cfg| cfg.name == name)
+                                {
+                                    *cfg = cfg.with_num(num);
+                                }
+                            }
+                            Ok(Err(err)) => {
+                                error!("Can not resize service {:?}: {:?}", name, err);
+                                changes.errors.push(format!(
+                                    "Can not resize service `{}` to {} workers: {:?}",
+                                    name, num, err));
+                            }
+                            Err(_) => changes.errors.push(format!(
+                                "Can not resize service `{}`: service is not available", name)),
+                        }
+                    }
+                    actix::fut::ok(changes)
+                }))
+    }
+
+    /// Reload service with new configuration
+    fn update_service(&self, srv: &Addr<Unsync, FeService>,
+                      cfg: ServiceConfig, ctx: &mut Context<Self>)
+    {
+        let name = cfg.name.clone();
+        srv.send(service::Configure(cfg))
+            .into_actor(self)
+            .then(move |res, _, _| {
+                match res {
+                    Ok(Ok(ReloadStatus::Failed(None))) =>
+                        error!("Service {:?} failed after configuration reload", name),
+                    Ok(Ok(ReloadStatus::Failed(Some(reason)))) =>
+                        error!("Service {:?} configuration reload failed: {:?}", name, reason),
+                    Ok(Err(err)) =>
+                        warn!("Service {:?} is {:?}, configuration is applied \
+                               on next reload", name, err),
+                    _ => (),
+                }
+                actix::fut::ok(())
+            }).spawn(ctx);
+    }
 }
 
 
@@ -241,6 +454,70 @@ impl Handler<StatusService> for CommandCenter {
     }
 }
 
+/// Recent output of service workers
+pub struct ServiceLogs(pub String, pub Option<usize>, pub usize);
+
+impl Message for ServiceLogs {
+    type Result = Result<Vec<LogLine>, CommandError>;
+}
+
+impl Handler<ServiceLogs> for CommandCenter {
+    type Result = Response<Vec<LogLine>, CommandError>;
+
+    fn handle(&mut self, msg: ServiceLogs, _: &mut Context<CommandCenter>) -> Self::Result {
+        match self.state {
+            State::Running => {
+                match self.services.get(&msg.0) {
+                    Some(service) =>
+                        Response::async(
+                            service.send(service::Logs{worker: msg.1, lines: msg.2})
+                                .then(|res| match res {
+                                    Ok(Ok(lines)) => Ok(lines),
+                                    Ok(Err(_)) => Err(CommandError::UnknownWorker),
+                                    Err(_) => Err(CommandError::UnknownService),
+                                })),
+                    None =>
+                        Response::reply(Err(CommandError::UnknownService)),
+                }
+            }
+            _ => Response::reply(Err(CommandError::NotReady))
+        }
+    }
+}
+
+/// Subscribe to output of service workers
+pub struct TailService(pub String, pub Option<usize>, pub usize,
+                       pub Recipient<Unsync, service::LogLines>);
+
+impl Message for TailService {
+    type Result = Result<(), CommandError>;
+}
+
+impl Handler<TailService> for CommandCenter {
+    type Result = Response<(), CommandError>;
+
+    fn handle(&mut self, msg: TailService, _: &mut Context<CommandCenter>) -> Self::Result {
+        match self.state {
+            State::Running => {
+                match self.services.get(&msg.0) {
+                    Some(service) =>
+                        Response::async(
+                            service.send(service::Tail{
+                                worker: msg.1, lines: msg.2, recipient: msg.3})
+                                .then(|res| match res {
+                                    Ok(Ok(_)) => Ok(()),
+                                    Ok(Err(_)) => Err(CommandError::UnknownWorker),
+                                    Err(_) => Err(CommandError::UnknownService),
+                                })),
+                    None =>
+                        Response::reply(Err(CommandError::UnknownService)),
+                }
+            }
+            _ => Response::reply(Err(CommandError::NotReady))
+        }
+    }
+}
+
 /// Pause service message
 pub struct PauseService(pub String);
 
@@ -274,6 +551,39 @@ impl Handler<PauseService> for CommandCenter {
     }
 }
 
+/// Change number of service workers
+pub struct ScaleService(pub String, pub u16);
+
+impl Message for ScaleService {
+    type Result = Result<(), CommandError>;
+}
+
+impl Handler<ScaleService> for CommandCenter {
+    type Result = Response<(), CommandError>;
+
+    fn handle(&mut self, msg: ScaleService, _: &mut Context<CommandCenter>) -> Self::Result {
+        match self.state {
+            State::Running => {
+                info!("Scale service {:?} to {} workers", msg.0, msg.1);
+                match self.services.get(&msg.0) {
+                    Some(service) =>
+                        Response::async(
+                            service.send(service::Scale(msg.1 as usize)).then(|res| match res {
+                                Ok(Ok(_)) => Ok(()),
+                                Ok(Err(err)) => Err(CommandError::Service(err)),
+                                Err(_) => Err(CommandError::UnknownService)
+                            })),
+                    None => Response::reply(Err(CommandError::UnknownService))
+                }
+            }
+            _ => {
+                warn!("Can not scale in system in `{:?}` state", self.state);
+                Response::reply(Err(CommandError::NotReady))
+            }
+        }
+    }
+}
+
 /// Resume service message
 pub struct ResumeService(pub String);
 
@@ -308,8 +618,8 @@ impl Handler<ResumeService> for CommandCenter {
     }
 }
 
-/// Reload service
-pub struct ReloadService(pub String, pub bool);
+/// Reload service, graceful flag and optional canary
+pub struct ReloadService(pub String, pub bool, pub Option<service::Canary>);
 
 impl Message for ReloadService {
     type Result = Result<ReloadStatus, CommandError>;
@@ -326,7 +636,7 @@ impl Handler<ReloadService> for CommandCenter {
                 match self.services.get(&msg.0) {
                     Some(service) =>
                         Response::async(
-                            service.send(service::Reload(graceful)).then(|res| match res {
+                            service.send(service::Reload(graceful, msg.2)).then(|res| match res {
                                 Ok(Ok(status)) => Ok(status),
                                 Ok(Err(err)) => Err(CommandError::Service(err)),
                                 Err(_) => Err(CommandError::UnknownService)
@@ -343,6 +653,79 @@ impl Handler<ReloadService> for CommandCenter {
     }
 }
 
+/// Deploy new revision of service configuration, `key=value` settings and optional canary
+pub struct DeployService(pub String, pub Vec<(String, String)>, pub Option<service::Canary>);
+
+impl Message for DeployService {
+    type Result = Result<ReloadStatus, CommandError>;
+}
+
+impl Handler<DeployService> for CommandCenter {
+    type Result = Response<ReloadStatus, CommandError>;
+
+    fn handle(&mut self, msg: DeployService, _: &mut Context<Self>) -> Self::Result {
+        match self.state {
+            State::Running => {
+                info!("Deploying service {:?}", msg.0);
+                let deploy = service::Deploy {
+                    settings: msg.1,
+                    directory: PathBuf::from(&self.cfg.master.directory),
+                    canary: msg.2,
+                };
+                match self.services.get(&msg.0) {
+                    Some(service) =>
+                        Response::async(
+                            service.send(deploy).then(|res| match res {
+                                Ok(Ok(status)) => Ok(status),
+                                Ok(Err(err)) => Err(CommandError::Service(err)),
+                                Err(_) => Err(CommandError::UnknownService)
+                            })),
+                    None =>
+                        Response::reply(Err(CommandError::UnknownService))
+                }
+            }
+            _ => {
+                warn!("Can not deploy in system in `{:?}` state", self.state);
+                Response::reply(Err(CommandError::NotReady))
+            }
+        }
+    }
+}
+
+/// Reload service with previous configuration revision
+pub struct RollbackService(pub String);
+
+impl Message for RollbackService {
+    type Result = Result<ReloadStatus, CommandError>;
+}
+
+impl Handler<RollbackService> for CommandCenter {
+    type Result = Response<ReloadStatus, CommandError>;
+
+    fn handle(&mut self, msg: RollbackService, _: &mut Context<Self>) -> Self::Result {
+        match self.state {
+            State::Running => {
+                info!("Rolling back service {:?}", msg.0);
+                match self.services.get(&msg.0) {
+                    Some(service) =>
+                        Response::async(
+                            service.send(service::Rollback).then(|res| match res {
+                                Ok(Ok(status)) => Ok(status),
+                                Ok(Err(err)) => Err(CommandError::Service(err)),
+                                Err(_) => Err(CommandError::UnknownService)
+                            })),
+                    None =>
+                        Response::reply(Err(CommandError::UnknownService))
+                }
+            }
+            _ => {
+                warn!("Can not rollback in system in `{:?}` state", self.state);
+                Response::reply(Err(CommandError::NotReady))
+            }
+        }
+    }
+}
+
 /// reload all services
 pub struct ReloadAll;
 
@@ -358,7 +741,7 @@ impl Handler<ReloadAll> for CommandCenter {
             State::Running => {
                 info!("reloading all services");
                 for srv in self.services.values() {
-                    srv.do_send(service::Reload(true));
+                    srv.do_send(service::Reload(true, None));
                 }
             }
             _ => warn!("Can not reload in system in `{:?}` state", self.state)
@@ -366,6 +749,30 @@ impl Handler<ReloadAll> for CommandCenter {
     }
 }
 
+/// Re-read configuration file, apply changes if `.0` is true
+pub struct ReloadConfig(pub bool);
+
+impl Message for ReloadConfig {
+    type Result = Result<ConfigChanges, CommandError>;
+}
+
+impl Handler<ReloadConfig> for CommandCenter {
+    type Result = ActorResponse<Self, ConfigChanges, CommandError>;
+
+    fn handle(&mut self, msg: ReloadConfig, ctx: &mut Context<Self>) -> Self::Result {
+        match self.state {
+            State::Running => {
+                info!("Reloading configuration file {:?}", self.cfg.master.config);
+                self.reload_config(msg.0, ctx)
+            }
+            _ => {
+                warn!("Can not reload configuration in `{:?}` state", self.state);
+                ActorResponse::reply(Err(CommandError::NotReady))
+            }
+        }
+    }
+}
+
 /// Handle ProcessEvent (SIGHUP, SIGINT, etc)
 impl Handler<signal::Signal> for CommandCenter {
     type Result = ();
@@ -377,8 +784,20 @@ impl Handler<signal::Signal> for CommandCenter {
                 self.stop(ctx, false);
             }
             signal::SignalType::Hup => {
-                info!("SIGHUP received, reloading");
-                // self.handle(ReloadAll, ctx);
+                info!("SIGHUP received, reloading configuration");
+                let addr: Addr<Unsync, _> = ctx.address();
+                addr.send(ReloadConfig(true))
+                    .into_actor(self)
+                    .then(|res, _, _| {
+                        match res {
+                            Ok(Ok(ref changes)) if changes.is_empty() =>
+                                info!("Configuration is not changed"),
+                            Ok(Ok(changes)) => info!("Configuration reloaded: {:?}", changes),
+                            Ok(Err(err)) => error!("Can not reload configuration: {:?}", err),
+                            Err(_) => (),
+                        }
+                        actix::fut::ok(())
+                    }).spawn(ctx);
             }
             signal::SignalType::Term => {
                 info!("SIGTERM received, stopping");
@@ -396,7 +815,9 @@ impl Handler<signal::Signal> for CommandCenter {
                         Ok(WaitStatus::Exited(pid, code)) => {
                             info!("Worker {} exit code: {}", pid, code);
                             let err = ProcessError::from(code);
-                            for srv in self.services.values_mut() {
+                            for srv in self.services.values_mut()
+                                .chain(self.retired.values_mut())
+                            {
                                 srv.do_send(
                                     service::ProcessExited(pid, err.clone())
                                 );
@@ -406,7 +827,9 @@ impl Handler<signal::Signal> for CommandCenter {
                         Ok(WaitStatus::Signaled(pid, sig, _)) => {
                             info!("Worker {} exit by signal {:?}", pid, sig);
                             let err = ProcessError::Signal(sig as usize);
-                            for srv in self.services.values_mut() {
+                            for srv in self.services.values_mut()
+                                .chain(self.retired.values_mut())
+                            {
                                 srv.do_send(
                                     service::ProcessExited(pid, err.clone())
                                 );
@@ -423,6 +846,25 @@ impl Handler<signal::Signal> for CommandCenter {
     }
 }
 
+/// SIGUSR1 signal, reopen service log files
+struct ReopenLogs;
+
+impl StreamHandler<ReopenLogs, ::std::io::Error> for CommandCenter {
+
+    fn handle(&mut self, _: ReopenLogs, _: &mut Context<Self>) {
+        info!("SIGUSR1 received, reopening log files");
+        for srv in self.services.values() {
+            srv.do_send(service::ReopenLogs);
+        }
+    }
+
+    fn error(&mut self, err: ::std::io::Error, _: &mut Context<Self>) -> Running {
+        error!("Can not handle SIGUSR1 signal: {}", err);
+        Running::Continue
+    }
+
+    fn finished(&mut self, _: &mut Context<Self>) {}
+}
 
 impl Actor for CommandCenter {
     type Context = Context<Self>;
@@ -435,6 +877,10 @@ impl Actor for CommandCenter {
         let addr: Addr<Syn, _> = ctx.address();
         Arbiter::system_registry().get::<signal::ProcessSignals>()
             .do_send(signal::Subscribe(addr.recipient()));
+        ctx.add_stream(
+            tokio_signal::unix::Signal::new(libc::SIGUSR1, Arbiter::handle())
+                .flatten_stream()
+                .map(|_| ReopenLogs));
 
         // start services
         for cfg in &self.cfg.services {
@@ -1,15 +1,20 @@
 use std;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::io::prelude::*;
 use std::ffi::OsString;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::os::unix::io::RawFd;
 
 use nix;
+use nix::sys::signal::Signal;
 use nix::unistd::{Gid, Uid};
 use toml;
-use structopt::StructOpt;
 
 use socket;
+use utils;
+use environ;
+use privileges;
 use config_helpers;
 
 pub struct Config {
@@ -28,8 +33,10 @@ pub struct Config {
 /// sock = "fectl.sock"
 /// directory = "/path/to/dir"
 /// ```
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MasterConfig {
+    /// Path to configuration file
+    pub config: OsString,
     /// Start master process in daemon mode
     pub daemon: bool,
     /// Path to file with process pid
@@ -43,6 +50,11 @@ pub struct MasterConfig {
     pub gid: Option<Gid>,
     /// Set uid id
     pub uid: Option<Uid>,
+    /// Set supplementary groups
+    pub groups: Option<Vec<Gid>>,
+
+    /// Root of cgroup v2 hierarchy for services
+    pub cgroup: Option<OsString>,
 
     /// Redirect stdout
     pub stdout: Option<String>,
@@ -97,7 +109,7 @@ struct TomlConfig {
 }
 
 #[derive(Deserialize, Debug)]
-struct TomlMasterConfig {
+pub(crate) struct TomlMasterConfig {
     #[serde(default = "config_helpers::default_sock")]
     pub sock: String,
     pub pid: Option<String>,
@@ -111,12 +123,18 @@ struct TomlMasterConfig {
     #[serde(deserialize_with="config_helpers::deserialize_uid_field")]
     pub uid: Option<Uid>,
 
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_groups_field")]
+    pub groups: Option<Vec<Gid>>,
+
+    pub cgroup: Option<String>,
+
     pub stdout: Option<String>,
     pub stderr: Option<String>,
 }
 
 
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Proto {
     tcp4,
@@ -124,6 +142,199 @@ pub enum Proto {
     unix,
 }
 
+/// Communication protocol between master and worker processes
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    /// Worker implements fectl pipe protocol
+    #[serde(rename="fectl")]
+    Fectl,
+    /// Plain process, considered loaded after successful exec, no heartbeats
+    #[serde(rename="none")]
+    None,
+}
+
+/// Step of worker stop sequence
+#[derive(Clone, Debug, PartialEq)]
+pub struct StopSignal {
+    pub signal: Signal,
+    /// Seconds to wait for worker exit before next step, default `shutdown_timeout`
+    pub timeout: Option<u64>,
+}
+
+/// Number of workers reloaded at once during rolling reload
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReloadBatch {
+    Count(usize),
+    /// Percent of service workers
+    Percent(u8),
+}
+
+impl ReloadBatch {
+
+    /// Batch size for service with `num` workers, at least one worker
+    pub fn size(&self, num: usize) -> usize {
+        match *self {
+            ReloadBatch::Count(count) => count,
+            ReloadBatch::Percent(percent) => (num * percent as usize).div_ceil(100),
+        }.max(1)
+    }
+}
+
+/// Worker health check
+///
+/// ```toml
+/// [service.readiness]
+/// type = "http"
+/// port = 8080
+/// path = "/health"
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ProbeConfig {
+    #[serde(rename="type")]
+    pub kind: ProbeKind,
+    /// Host for tcp and http probes, default "127.0.0.1"
+    #[serde(default="config_helpers::default_probe_host")]
+    pub host: String,
+    pub port: Option<u16>,
+    /// Url path for http probe, file path for file probe
+    pub path: Option<String>,
+    /// Command for exec probe
+    #[serde(default="config_helpers::default_vec")]
+    pub command: Vec<String>,
+    /// Seconds between probes, default 5
+    #[serde(default="config_helpers::default_probe_interval")]
+    pub interval: u32,
+    /// Probe timeout in seconds, default 1
+    #[serde(default="config_helpers::default_probe_timeout")]
+    pub timeout: u32,
+    /// Number of consecutive liveness probe failures before worker restart, default 3
+    #[serde(default="config_helpers::default_probe_threshold")]
+    pub threshold: u32,
+    /// Address of `host` and `port`, resolved at configuration loading
+    #[serde(skip)]
+    pub addr: Option<SocketAddr>,
+}
+
+impl ProbeConfig {
+
+    fn resolve(&mut self) -> Result<(), String> {
+        match self.kind {
+            ProbeKind::Tcp | ProbeKind::Http => if self.port.is_none() {
+                return Err("`port` is required for tcp and http probes".to_owned())
+            },
+            ProbeKind::Exec => if self.command.is_empty() {
+                return Err("`command` is required for exec probe".to_owned())
+            },
+            ProbeKind::File => if self.path.is_none() {
+                return Err("`path` is required for file probe".to_owned())
+            },
+        }
+        if self.interval == 0 || self.timeout == 0 || self.threshold == 0 {
+            return Err("`interval`, `timeout` and `threshold` must be greater than 0".to_owned())
+        }
+        // probes run on master event loop, do not block it with dns lookups
+        if let (ProbeKind::Tcp, Some(port)) | (ProbeKind::Http, Some(port)) = (self.kind, self.port) {
+            self.addr = (self.host.as_str(), port).to_socket_addrs()
+                .map_err(|err| format!("Can not resolve {}:{}: {}", self.host, port, err))?
+                .next();
+            if self.addr.is_none() {
+                return Err(format!("Can not resolve {}:{}", self.host, port))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Automatic scaling of workers by load reported in worker heartbeats
+///
+/// ```toml
+/// [service.autoscale]
+/// min_workers = 2
+/// max_workers = 16
+/// capacity = 100
+/// ```
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct AutoscaleConfig {
+    /// Minimal number of workers, default 1
+    #[serde(default="config_helpers::default_min_workers")]
+    pub min_workers: u16,
+    /// Maximal number of workers
+    pub max_workers: u16,
+    /// Number of concurrent requests one worker is able to handle
+    pub capacity: u32,
+    /// Target utilization of workers capacity, in percents, default 70
+    #[serde(default="config_helpers::default_target_utilization")]
+    pub target_utilization: u8,
+    /// Window of load moving average, in seconds, default 60
+    #[serde(default="config_helpers::default_autoscale_window")]
+    pub window: u32,
+    /// Seconds after previous scaling before workers get added, default 60
+    #[serde(default="config_helpers::default_scale_up_cooldown")]
+    pub scale_up_cooldown: u32,
+    /// Seconds after previous scaling before workers get removed, default 300
+    #[serde(default="config_helpers::default_scale_down_cooldown")]
+    pub scale_down_cooldown: u32,
+}
+
+impl AutoscaleConfig {
+
+    fn validate(&self, num: u16) -> Result<(), String> {
+        if self.min_workers == 0 || self.max_workers < self.min_workers {
+            return Err("`min_workers` must be in 1..max_workers range".to_owned())
+        }
+        if !(self.min_workers..=self.max_workers).contains(&num) {
+            return Err("`num` must be in min_workers..max_workers range".to_owned())
+        }
+        if self.capacity == 0 || self.window == 0 {
+            return Err("`capacity` and `window` must be greater than 0".to_owned())
+        }
+        if !(1..=100).contains(&self.target_utilization) {
+            return Err("`target_utilization` must be in 1..100 range".to_owned())
+        }
+        Ok(())
+    }
+}
+
+/// Type of worker health check
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ProbeKind {
+    /// Connect to `host` and `port`
+    #[serde(rename="tcp")]
+    Tcp,
+    /// GET request to `path`, expects 2xx response status
+    #[serde(rename="http")]
+    Http,
+    /// Run `command`, expects 0 exit code
+    #[serde(rename="exec")]
+    Exec,
+    /// Check if file at `path` exists
+    #[serde(rename="file")]
+    File,
+}
+
+/// Restart policy of worker processes
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RestartPolicy {
+    /// Restart worker after any exit
+    #[serde(rename="always")]
+    Always,
+    /// Restart worker if it exits with non-zero code or gets killed
+    #[serde(rename="on-failure")]
+    OnFailure,
+    /// Do not restart worker
+    #[serde(rename="never")]
+    Never,
+}
+
+/// Cpu affinity of worker processes
+#[derive(Clone, Debug, PartialEq)]
+pub enum CpuAffinity {
+    /// Pin each worker to its own cpu, by worker index
+    Worker,
+    /// Run workers on specified cpus
+    Cpus(Vec<usize>),
+}
+
 /// Socket configuration
 ///
 /// ```toml
@@ -134,12 +345,33 @@ pub enum Proto {
 /// service = ["test"]
 /// loader = "aiohttp"
 /// arguments = ["arg1", "arg2", "arg3"]
+///
+/// [[socket]]
+/// name = "backend"
+/// proto = "unix"
+/// path = "/var/run/backend.sock"
+/// mode = "0660"
+/// group = "nginx"
 /// ```
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct SocketConfig {
     pub name: String,
-    pub port: u32,
+    pub port: Option<u32>,
     pub host: Option<String>,
+    /// Path of unix domain socket
+    pub path: Option<String>,
+    /// Permissions of unix domain socket file
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_mode_field")]
+    pub mode: Option<u32>,
+    /// Owner of unix domain socket file
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_uid_field")]
+    pub owner: Option<Uid>,
+    /// Group of unix domain socket file
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_gid_field")]
+    pub group: Option<Gid>,
     #[serde(default = "config_helpers::default_backlog")]
     pub backlog: u16,
     #[serde(default = "config_helpers::default_proto")]
@@ -151,7 +383,7 @@ pub struct SocketConfig {
     pub arguments: Vec<String>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct ServiceConfig {
     /// Service name
     pub name: String,
@@ -160,12 +392,64 @@ pub struct ServiceConfig {
     pub num: u16,
 
     /// Worker start command
+    ///
+    /// Command line is split into arguments with shell quoting rules.
+    #[serde(default)]
     pub command: String,
 
-    /// Number of restarts before marking worker as failed, default 3
+    /// Worker start command as list of arguments, alternative to `command`
+    #[serde(default = "config_helpers::default_vec")]
+    pub args: Vec<String>,
+
+    /// Run `command` with `/bin/sh -c`
+    #[serde(default)]
+    pub shell: bool,
+
+    /// Resolved worker command line
+    #[serde(skip)]
+    pub argv: Vec<String>,
+
+    /// Worker communication protocol, "fectl" or "none", default "fectl"
+    #[serde(default="config_helpers::default_protocol")]
+    pub protocol: Protocol,
+
+    /// Restart policy, default "always"
+    #[serde(default="config_helpers::default_restart_policy")]
+    pub restart: RestartPolicy,
+
+    /// Number of restarts within `restart_window` before marking worker as failed, default 3
     #[serde(default="config_helpers::default_restarts")]
     pub restarts: u16,
 
+    /// Sliding window for counting worker restarts, in seconds, default 60
+    #[serde(default="config_helpers::default_restart_window")]
+    pub restart_window: u32,
+
+    /// Delay before first restart of failed worker, in seconds, default 1
+    #[serde(default="config_helpers::default_backoff_initial")]
+    pub backoff_initial: f64,
+
+    /// Maximum delay before restart, in seconds, default 60
+    #[serde(default="config_helpers::default_backoff_max")]
+    pub backoff_max: f64,
+
+    /// Delay multiplier for each subsequent restart within `restart_window`, default 2
+    #[serde(default="config_helpers::default_backoff_multiplier")]
+    pub backoff_multiplier: f64,
+
+    /// Random delay variation, fraction of the delay, default 0.1
+    #[serde(default="config_helpers::default_backoff_jitter")]
+    pub backoff_jitter: f64,
+
+    /// Retry failed service after this many seconds, delay doubles with each attempt
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_duration_field")]
+    pub recover_after: Option<u64>,
+
+    /// Number of recovery attempts before giving up, default 5
+    #[serde(default="config_helpers::default_recover_attempts")]
+    pub recover_attempts: u16,
+
     /// Change to specified directory before service worker loading.
     pub directory: Option<String>,
 
@@ -187,6 +471,22 @@ pub struct ServiceConfig {
     #[serde(deserialize_with="config_helpers::deserialize_uid_field")]
     pub uid: Option<Uid>,
 
+    /// Supplementary groups of worker processes.
+    ///
+    /// List of group names or group ids, by default groups of `uid` user
+    /// are loaded from group database.
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_groups_field")]
+    pub groups: Option<Vec<Gid>>,
+
+    /// Linux capabilities retained after switching to `uid`, i.e. `CAP_NET_BIND_SERVICE`
+    #[serde(default = "config_helpers::default_vec")]
+    pub capabilities: Vec<String>,
+
+    /// Set `no_new_privs` flag for worker processes
+    #[serde(default)]
+    pub no_new_privs: bool,
+
     /// Workers silent for more than this many seconds are killed and restarted.
     ///
     /// Generally set to ten seconds. Only set this noticeably higher if
@@ -212,16 +512,312 @@ pub struct ServiceConfig {
     #[serde(default="config_helpers::default_shutdown_timeout")]
     pub shutdown_timeout: u32,
 
-    /// A path to a file where `fectld` should redirect `stdout` for this service.
+    /// Signals sent to worker on stop, i.e. `["SIGINT:10", "SIGTERM:5"]`
+    ///
+    /// Each signal is followed by wait for worker exit, workers still alive
+    /// after last step are force killed. Default is `SIGTERM` and `shutdown_timeout`.
+    #[serde(default="config_helpers::default_stop_signals")]
+    #[serde(deserialize_with="config_helpers::deserialize_stop_signals_field")]
+    pub stop_signals: Vec<StopSignal>,
+
+    /// Worker is considered started after readiness probe succeeds
+    pub readiness: Option<ProbeConfig>,
+
+    /// Worker gets restarted after `threshold` consecutive liveness probe failures
+    pub liveness: Option<ProbeConfig>,
+
+    /// Gracefully reload worker when its resident memory exceeds this size
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_size_field")]
+    pub max_rss: Option<u64>,
+
+    /// Gracefully reload worker after it used this much cpu time, in seconds
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_duration_field")]
+    pub max_cpu_seconds: Option<u64>,
+
+    /// Gracefully reload worker after it runs this many seconds
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_duration_field")]
+    pub max_lifetime: Option<u64>,
+
+    /// Random addition to `max_lifetime` of each worker process, in seconds
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_duration_field")]
+    pub lifetime_jitter: Option<u64>,
+
+    /// Reload workers in batches of this size, number of workers or percent
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_reload_batch_field")]
+    pub reload_batch: Option<ReloadBatch>,
+
+    /// Pause between reload batches, in seconds
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_duration_field")]
+    pub reload_pause: Option<u64>,
+
+    /// Add and remove workers by load reported in worker heartbeats
+    pub autoscale: Option<AutoscaleConfig>,
+
+    /// Number of configuration revisions remembered for rollback, default 5
+    #[serde(default="config_helpers::default_revision_history")]
+    pub revision_history: u16,
+
+    /// A path to a file where `fectld` should write `stdout` of service workers.
     ///
-    /// By default redirect for stdout is not enabled
+    /// By default worker output is written to master's stdout
     pub stdout: Option<String>,
 
-    /// A path to a file where `fectld` should redirect `stderr` for this service.
+    /// A path to a file where `fectld` should write `stderr` of service workers.
     ///
-    /// By default redirect for stderr is not enabled
+    /// By default worker output is written to master's stderr
     pub stderr: Option<String>,
 
+    /// Rotate log files when size exceeds this value
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_size_field")]
+    pub log_max_size: Option<u64>,
+
+    /// Rotate log files after this many seconds
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_duration_field")]
+    pub log_max_age: Option<u64>,
+
+    /// Number of rotated log files to keep, default 5
+    #[serde(default="config_helpers::default_log_backups")]
+    pub log_backups: u16,
+
+    /// Environment variables for worker processes, list of `KEY=VALUE` entries.
+    ///
+    /// `${VAR}` references are expanded from master process environment.
+    #[serde(default = "config_helpers::default_vec")]
+    pub environ: Vec<String>,
+
+    /// A path to dotenv-style file with environment variables for worker processes.
+    ///
+    /// Variables from `environ` override variables from this file.
+    pub env_file: Option<String>,
+
+    /// Resolved worker environment
+    #[serde(skip)]
+    pub env: Vec<(String, String)>,
+
+    /// Maximum number of open file descriptors
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_rlimit_field")]
+    pub rlimit_nofile: Option<u64>,
+
+    /// Maximum size of core file
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_rlimit_field")]
+    pub rlimit_core: Option<u64>,
+
+    /// Maximum size of process's virtual memory
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_rlimit_field")]
+    pub rlimit_as: Option<u64>,
+
+    /// Maximum number of processes of worker's user
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_rlimit_field")]
+    pub rlimit_nproc: Option<u64>,
+
+    /// File mode creation mask of worker processes
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_mode_field")]
+    pub umask: Option<u32>,
+
+    /// Scheduling priority of worker processes, -20..19
+    pub nice: Option<i32>,
+
+    /// OOM killer score adjustment of worker processes, -1000..1000
+    pub oom_score_adj: Option<i32>,
+
+    /// Cpu affinity, list of cpus or `"worker"` to pin each worker to its own cpu
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_cpu_affinity_field")]
+    pub cpu_affinity: Option<CpuAffinity>,
+
+    /// Memory limit of service cgroup, shared by all workers
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_size_field")]
+    pub memory_max: Option<u64>,
+
+    /// Relative cpu weight of service cgroup, 1..10000
+    pub cpu_weight: Option<u32>,
+
+    /// Cpu bandwidth limit of service cgroup
+    #[serde(default)]
+    #[serde(deserialize_with="config_helpers::deserialize_cpu_max_field")]
+    pub cpu_max: Option<String>,
+
+    /// Maximum number of processes in service cgroup
+    pub pids_max: Option<u64>,
+
+    /// Service cgroup path
+    #[serde(skip)]
+    pub cgroup: Option<PathBuf>,
+
+    /// Additional file descriptors inherited by worker processes.
+    ///
+    /// All other descriptors except stdio, worker pipes and service
+    /// listeners are closed before command execution.
+    #[serde(default = "config_helpers::default_vec")]
+    pub inherit_fds: Vec<RawFd>,
+
+    /// Listeners passed to worker processes
+    #[serde(skip)]
+    pub sockets: Vec<socket::ServiceSocket>,
+
+    /// Service table of configuration file, base for revised configurations
+    #[serde(skip)]
+    pub source: toml::value::Table,
+}
+
+impl ServiceConfig {
+
+    /// resolve settings that depend on master configuration
+    pub fn resolve(&mut self, directory: &Path) -> Result<(), String> {
+        self.argv = self.command_line()
+            .map_err(|err| format!("Service `{}`: {}", self.name, err))?;
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                return Err(format!("Service `{}`: `nice` must be in -20..19 range", self.name))
+            }
+        }
+        if let Some(score) = self.oom_score_adj {
+            if !(-1000..=1000).contains(&score) {
+                return Err(format!(
+                    "Service `{}`: `oom_score_adj` must be in -1000..1000 range", self.name))
+            }
+        }
+        if let Some(weight) = self.cpu_weight {
+            if !(1..=10_000).contains(&weight) {
+                return Err(format!("Service `{}`: `cpu_weight` must be in 1..10000 range", self.name))
+            }
+        }
+        if self.backoff_initial < 0.0 {
+            return Err(format!("Service `{}`: `backoff_initial` must not be negative", self.name))
+        }
+        if self.backoff_max < self.backoff_initial {
+            return Err(format!("Service `{}`: `backoff_max` must not be less than `backoff_initial`",
+                               self.name))
+        }
+        if self.backoff_multiplier < 1.0 {
+            return Err(format!("Service `{}`: `backoff_multiplier` must be at least 1", self.name))
+        }
+        if !(0.0..=1.0).contains(&self.backoff_jitter) {
+            return Err(format!("Service `{}`: `backoff_jitter` must be in 0..1 range", self.name))
+        }
+        if self.recover_after == Some(0) {
+            return Err(format!("Service `{}`: `recover_after` must be greater than 0", self.name))
+        }
+        if self.max_lifetime == Some(0) {
+            return Err(format!("Service `{}`: `max_lifetime` must be greater than 0", self.name))
+        }
+        if let Some(ref mut probe) = self.readiness {
+            probe.resolve().map_err(|err| format!("Service `{}`: readiness: {}", self.name, err))?;
+        }
+        if let Some(ref mut probe) = self.liveness {
+            probe.resolve().map_err(|err| format!("Service `{}`: liveness: {}", self.name, err))?;
+        }
+        if let Some(ref autoscale) = self.autoscale {
+            autoscale.validate(self.num)
+                .map_err(|err| format!("Service `{}`: autoscale: {}", self.name, err))?;
+            if self.protocol == Protocol::None {
+                return Err(format!(
+                    "Service `{}`: autoscale requires load reports of `fectl` protocol", self.name))
+            }
+        }
+        for cap in &self.capabilities {
+            if privileges::capability(cap).is_none() {
+                return Err(format!("Service `{}`: Unknown capability {:?}", self.name, cap))
+            }
+        }
+        self.env = environ::load(self, directory)
+            .map_err(|err| format!("Service `{}`: {}", self.name, err))?;
+        Ok(())
+    }
+
+    /// New configuration with `key=value` settings applied,
+    /// value is parsed as toml value or used as plain string
+    pub fn revise(&self, directory: &Path, settings: &[(String, String)])
+                  -> Result<ServiceConfig, String>
+    {
+        let mut table = self.source.clone();
+        for (key, value) in settings {
+            let value = toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or_else(|| toml::Value::String(value.clone()));
+
+            // `command` and `args` replace each other
+            match key.as_str() {
+                "name" | "num" =>
+                    return Err(format!("Service `{}`: `{}` can not be revised", self.name, key)),
+                "command" => { table.remove("args"); }
+                "args" => { table.remove("command"); }
+                _ => (),
+            }
+
+            // dotted key sets value of nested table, i.e. `readiness.path=/health`
+            let mut parts: Vec<&str> = key.split('.').collect();
+            let last = parts.pop().unwrap_or("");
+            let mut current = &mut table;
+            for part in parts {
+                let entry = current.entry(part.to_owned())
+                    .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+                current = match *entry {
+                    toml::Value::Table(ref mut nested) => nested,
+                    _ => return Err(
+                        format!("Service `{}`: `{}` is not a table", self.name, part)),
+                };
+            }
+            current.insert(last.to_owned(), value);
+        }
+
+        let mut cfg: ServiceConfig = toml::Value::Table(table.clone()).try_into()
+            .map_err(|err| format!("Service `{}`: {}", self.name, err))?;
+        cfg.source = table;
+        cfg.resolve(directory)?;
+        cfg.cgroup = self.cgroup.clone();
+        cfg.sockets = self.sockets.clone();
+        Ok(cfg)
+    }
+
+    /// Same configuration with different number of workers
+    pub fn with_num(&self, num: u16) -> ServiceConfig {
+        let mut cfg = self.clone();
+        cfg.num = num;
+        cfg.source.insert("num".to_owned(), toml::Value::Integer(i64::from(num)));
+        cfg
+    }
+
+    fn command_line(&self) -> Result<Vec<String>, String> {
+        let argv = self.parse_command_line()?;
+        if let Some(arg) = argv.iter().find(|arg| arg.contains('\0')) {
+            return Err(format!("Command argument contains nul byte: {:?}", arg))
+        }
+        Ok(argv)
+    }
+
+    fn parse_command_line(&self) -> Result<Vec<String>, String> {
+        if !self.args.is_empty() {
+            if !self.command.is_empty() || self.shell {
+                return Err("`args` can not be used with `command` or `shell`".to_owned())
+            }
+            return Ok(self.args.clone())
+        }
+        if self.command.trim().is_empty() {
+            return Err("`command` or `args` is required".to_owned())
+        }
+        if self.shell {
+            Ok(vec!["/bin/sh".to_owned(), "-c".to_owned(), self.command.clone()])
+        } else {
+            utils::split_command(&self.command)
+                .map_err(|err| format!("Can not parse command {:?}: {}", self.command, err))
+        }
+    }
 }
 
 /// Loging configuration
@@ -253,32 +849,81 @@ impl Default for LoggingConfig {
 
 /// Command line arguments
 #[derive(StructOpt, Debug)]
-struct Cli {
+pub(crate) struct Cli {
     /// Sets a custom config file for fectld
     #[structopt(long="config", short="c", default_value="fectld.toml")]
-    config: String,
+    pub config: String,
 
     /// Run in background
     #[structopt(long="daemon", short="d")]
-    daemon: bool,
-}
+    pub daemon: bool,
 
+    /// Validate configuration file and exit
+    #[structopt(long="check-config")]
+    pub check_config: bool,
+}
 
-pub fn load_config() -> Option<Config> {
-    let args = Cli::from_args();
 
+fn read_config(path: &Path) -> Result<TomlConfig, String> {
     let mut cfg_str = String::new();
-    if let Err(err) = std::fs::File::open(args.config)
+    if let Err(err) = std::fs::File::open(path)
         .and_then(|mut f| f.read_to_string(&mut cfg_str))
     {
-        println!("Can not read configuration file due to: {}", err.description());
-        return None
+        return Err(format!("Can not read configuration file due to: {}", err.description()))
     }
 
-    let cfg: TomlConfig = match toml::from_str(&cfg_str) {
+    let mut cfg: TomlConfig = toml::from_str(&cfg_str)
+        .map_err(|err| format!("Can not parse config file: {}", err))?;
+
+    // keep service tables for revised configurations
+    let value: toml::Value = toml::from_str(&cfg_str)
+        .map_err(|err| format!("Can not parse config file: {}", err))?;
+    if let Some(tables) = value.get("service").and_then(|v| v.as_array()) {
+        for (srv, table) in cfg.service.iter_mut().zip(tables) {
+            if let Some(table) = table.as_table() {
+                srv.source = table.clone();
+            }
+        }
+    }
+    Ok(cfg)
+}
+
+fn resolve_services(mut services: Vec<ServiceConfig>, directory: &Path, cgroup: Option<&OsString>)
+                    -> Result<Vec<ServiceConfig>, String>
+{
+    for srv in &mut services {
+        srv.resolve(directory)?;
+        srv.cgroup = cgroup.map(|root| Path::new(root).join(&srv.name));
+    }
+    Ok(services)
+}
+
+/// Re-read sockets and services configuration.
+///
+/// Master process configuration can not be changed at runtime.
+pub fn reload_config(master: &MasterConfig)
+                     -> Result<(Vec<SocketConfig>, Vec<ServiceConfig>), String>
+{
+    let cfg = read_config(Path::new(&master.config))?;
+    let services = resolve_services(
+        cfg.service, Path::new(&master.directory), master.cgroup.as_ref())?;
+    Ok((cfg.socket, services))
+}
+
+pub(crate) fn load_config(args: Cli) -> Option<Config> {
+    // canonizalize config path, master process changes working directory
+    let path = match std::fs::canonicalize(&args.config) {
+        Ok(path) => path,
+        Err(err) => {
+            println!("Can not read configuration file due to: {}", err);
+            return None
+        }
+    };
+
+    let cfg = match read_config(&path) {
         Ok(cfg) => cfg,
         Err(err) => {
-            println!("Can not parse config file: {}", err);
+            println!("{}", err);
             return None
         }
     };
@@ -290,6 +935,8 @@ pub fn load_config() -> Option<Config> {
         pid: None,
         gid: None,
         uid: None,
+        groups: None,
+        cgroup: None,
         stdout: None,
         stderr: None,
     });
@@ -318,6 +965,8 @@ pub fn load_config() -> Option<Config> {
     };
 
     let master = MasterConfig {
+        config: path.into_os_string(),
+
         // set default value from command line
         daemon: args.daemon,
 
@@ -327,6 +976,8 @@ pub fn load_config() -> Option<Config> {
         pid,
         gid: toml_master.gid,
         uid: toml_master.uid,
+        groups: toml_master.groups,
+        cgroup: toml_master.cgroup.map(|cgroup| Path::new(&directory).join(cgroup).into_os_string()),
 
         // check if working directory exists
         directory,
@@ -345,10 +996,115 @@ pub fn load_config() -> Option<Config> {
         }
     };
 
+    // services config
+    let mut services = match resolve_services(
+        cfg.service, Path::new(&master.directory), master.cgroup.as_ref()) {
+        Ok(services) => services,
+        Err(err) => {
+            println!("{}", err);
+            return None
+        }
+    };
+    for srv in &mut services {
+        srv.sockets = socket::Socket::service_sockets(&sockets, &srv.name);
+    }
+
     Some(Config {
         master,
         sockets,
-        services: cfg.service,
+        services,
         logging: cfg.logging.unwrap_or(LoggingConfig::default()),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    fn config() -> ServiceConfig {
+        let source = r#"
+            name = "test"
+            num = 2
+            command = "app --port 8080"
+
+            [readiness]
+            type = "file"
+            path = "/tmp/ready"
+        "#;
+        let mut cfg: ServiceConfig = toml::from_str(source).unwrap();
+        cfg.source = toml::from_str(source).unwrap();
+        cfg.resolve(Path::new("/")).unwrap();
+        cfg
+    }
+
+    fn revise(cfg: &ServiceConfig, settings: &[(&str, &str)]) -> Result<ServiceConfig, String> {
+        let settings: Vec<_> = settings.iter()
+            .map(|&(key, value)| (key.to_owned(), value.to_owned())).collect();
+        cfg.revise(Path::new("/"), &settings)
+    }
+
+    #[test]
+    fn test_reload_batch_size() {
+        assert_eq!(ReloadBatch::Count(2).size(10), 2);
+        assert_eq!(ReloadBatch::Percent(25).size(10), 3);
+        assert_eq!(ReloadBatch::Percent(50).size(10), 5);
+        assert_eq!(ReloadBatch::Percent(100).size(10), 10);
+        assert_eq!(ReloadBatch::Percent(1).size(10), 1);
+        // at least one worker
+        assert_eq!(ReloadBatch::Count(0).size(10), 1);
+        assert_eq!(ReloadBatch::Percent(10).size(0), 1);
+    }
+
+    #[test]
+    fn test_revise_dotted_keys() {
+        let cfg = revise(&config(), &[("readiness.path", "/tmp/other"),
+                                      ("readiness.interval", "5")]).unwrap();
+        let probe = cfg.readiness.unwrap();
+        assert_eq!(probe.path, Some("/tmp/other".to_owned()));
+        assert_eq!(probe.interval, 5);
+        assert_eq!(probe.kind, ProbeKind::File);
+
+        // nested table is created on demand
+        let cfg = revise(&config(), &[("liveness.type", "file"),
+                                      ("liveness.path", "/tmp/alive")]).unwrap();
+        assert_eq!(cfg.liveness.unwrap().path, Some("/tmp/alive".to_owned()));
+
+        assert!(revise(&config(), &[("command.path", "x")]).is_err());
+    }
+
+    #[test]
+    fn test_revise_values() {
+        // valid toml value keeps its type, anything else is a string
+        let cfg = revise(&config(), &[("timeout", "30"),
+                                      ("stdout", "/var/log/app.log"),
+                                      ("stderr", "\"/var/log/err.log\"")]).unwrap();
+        assert_eq!(cfg.timeout, 30);
+        assert_eq!(cfg.stdout, Some("/var/log/app.log".to_owned()));
+        assert_eq!(cfg.stderr, Some("/var/log/err.log".to_owned()));
+        assert_eq!(cfg.source.get("timeout"), Some(&toml::Value::Integer(30)));
+
+        // quoted number is a string
+        assert!(revise(&config(), &[("timeout", "\"30\"")]).is_err());
+    }
+
+    #[test]
+    fn test_revise_command_args() {
+        let cfg = revise(&config(), &[("args", r#"["app", "--port", "9090"]"#)]).unwrap();
+        assert_eq!(cfg.argv, vec!["app", "--port", "9090"]);
+        assert!(cfg.command.is_empty());
+        assert!(cfg.source.get("command").is_none());
+
+        let cfg = revise(&cfg, &[("command", "other --flag")]).unwrap();
+        assert_eq!(cfg.argv, vec!["other", "--flag"]);
+        assert!(cfg.args.is_empty());
+        assert!(cfg.source.get("args").is_none());
+    }
+
+    #[test]
+    fn test_revise_rejected_keys() {
+        assert!(revise(&config(), &[("name", "other")]).is_err());
+        assert!(revise(&config(), &[("num", "4")]).is_err());
+        assert!(revise(&config(), &[("timeout", "30"), ("num", "4")]).is_err());
+    }
+}
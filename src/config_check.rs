@@ -0,0 +1,300 @@
+// Configuration file validation, `fectld --check-config`
+use std;
+use std::path::{Path, PathBuf};
+use std::ffi::CString;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStrExt;
+
+use libc;
+use toml;
+
+use utils;
+use config::{Proto, ServiceConfig, SocketConfig, LoggingConfig, TomlMasterConfig};
+
+
+/// Location of toml table in configuration file
+#[derive(Default)]
+struct Table {
+    line: usize,
+    keys: HashMap<String, usize>,
+}
+
+impl Table {
+    fn key(&self, key: &str) -> usize {
+        self.keys.get(key).cloned().unwrap_or(self.line)
+    }
+}
+
+/// Line numbers of configuration file sections
+#[derive(Default)]
+struct Locations {
+    master: Table,
+    logging: Table,
+    sockets: Vec<Table>,
+    services: Vec<Table>,
+}
+
+impl Locations {
+    fn parse(content: &str) -> Locations {
+        let mut locs = Locations::default();
+        let mut current: Option<&mut Table> = None;
+
+        for (num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                let table = Table{line: num + 1, keys: HashMap::new()};
+                current = match line.trim_matches(|c| c == '[' || c == ']').trim() {
+                    "master" => { locs.master = table; Some(&mut locs.master) },
+                    "logging" => { locs.logging = table; Some(&mut locs.logging) },
+                    "socket" => { locs.sockets.push(table); locs.sockets.last_mut() },
+                    "service" => { locs.services.push(table); locs.services.last_mut() },
+                    _ => None,
+                };
+            } else if let Some(ref mut table) = current {
+                if let Some(pos) = line.find('=') {
+                    let key = line[..pos].trim();
+                    if !key.is_empty() &&
+                        key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    {
+                        table.keys.insert(key.to_owned(), num + 1);
+                    }
+                }
+            }
+        }
+        locs
+    }
+}
+
+/// Collected configuration problems
+struct Diagnostics {
+    path: PathBuf,
+    errors: Vec<(usize, String)>,
+}
+
+impl Diagnostics {
+    fn error(&mut self, line: usize, msg: String) {
+        self.errors.push((line, msg));
+    }
+
+    /// Deserialize toml table, report error location
+    fn load<T>(&mut self, value: toml::Value, table: &Table, name: &str) -> Option<T>
+        where T: for<'de> ::serde::Deserialize<'de>
+    {
+        match value.try_into::<T>() {
+            Ok(val) => Some(val),
+            Err(err) => {
+                let msg = format!("{}", err);
+                let line = match msg.find("for key `") {
+                    Some(pos) => {
+                        let key = &msg[pos+9..];
+                        let key = &key[..key.find('`').unwrap_or(key.len())];
+                        table.key(key.rsplit('.').next().unwrap_or(key))
+                    }
+                    None => table.line,
+                };
+                self.error(line, format!("{}: {}", name, msg));
+                None
+            }
+        }
+    }
+
+    fn print(&self) {
+        for &(line, ref msg) in &self.errors {
+            println!("{}:{}: {}", self.path.display(), line, msg);
+        }
+    }
+}
+
+/// Validate configuration file without starting master process.
+///
+/// All found problems are printed, returns false if configuration is not valid.
+pub fn check(path: &str) -> bool {
+    let path = match std::fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(err) => {
+            println!("Can not read configuration file {}: {}", path, err);
+            return false
+        }
+    };
+    let mut content = String::new();
+    if let Err(err) = std::fs::File::open(&path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+    {
+        println!("Can not read configuration file {}: {}", path.display(), err);
+        return false
+    }
+    let cfg = match content.parse::<toml::Value>() {
+        Ok(toml::Value::Table(cfg)) => cfg,
+        Ok(_) => unreachable!(),
+        Err(err) => {
+            let line = err.line_col().map(|pos| pos.0 + 1).unwrap_or(1);
+            println!("{}:{}: Can not parse config file: {}", path.display(), line, err);
+            return false
+        }
+    };
+
+    let locs = Locations::parse(&content);
+    let mut diag = Diagnostics{path: path.clone(), errors: Vec::new()};
+
+    // master section
+    let mut directory = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(err) => {
+            println!("Can not get current directory: {}", err);
+            return false
+        }
+    };
+    if let Some(master) = cfg.get("master").cloned() {
+        if let Some(master) = diag.load::<TomlMasterConfig>(master, &locs.master, "master") {
+            if let Some(ref dir) = master.directory {
+                match std::fs::canonicalize(dir) {
+                    Ok(dir) => directory = dir,
+                    Err(err) => diag.error(
+                        locs.master.key("directory"),
+                        format!("master: Error accessing working directory {:?}: {}", dir, err)),
+                }
+            }
+            for &(key, file) in &[("stdout", &master.stdout), ("stderr", &master.stderr)] {
+                if let Some(ref file) = *file {
+                    if let Err(err) = check_writable(&directory.join(file)) {
+                        diag.error(locs.master.key(key), format!(
+                            "master: Can not write {} file {:?}: {}", key, file, err));
+                    }
+                }
+            }
+        }
+    }
+
+    // logging section
+    if let Some(logging) = cfg.get("logging").cloned() {
+        diag.load::<LoggingConfig>(logging, &locs.logging, "logging");
+    }
+
+    // services, sockets could refer to services with invalid settings
+    let mut services = Vec::new();
+    let service_names: Vec<&str> = cfg.get("service").and_then(|v| v.as_array())
+        .map(|entries| entries.iter()
+             .filter_map(|entry| entry.get("name").and_then(|v| v.as_str())).collect())
+        .unwrap_or_default();
+    if let Some(entries) = cfg.get("service").and_then(|v| v.as_array()) {
+        for (idx, entry) in entries.iter().enumerate() {
+            let table = locs.services.get(idx).unwrap_or(&locs.master);
+            let name = entry.get("name").and_then(|v| v.as_str())
+                .map(|n| format!("service `{}`", n)).unwrap_or_else(|| "service".to_owned());
+
+            if let Some(mut srv) = diag.load::<ServiceConfig>(entry.clone(), table, &name) {
+                if services.iter().any(|s: &ServiceConfig| s.name == srv.name) {
+                    diag.error(table.key("name"), format!("{}: duplicate service name", name));
+                }
+                if srv.num == 0 {
+                    diag.error(table.key("num"), format!("{}: `num` must be greater than 0", name));
+                }
+                check_service(&mut srv, &directory, table, &name, &mut diag);
+                services.push(srv);
+            }
+        }
+    }
+
+    // sockets
+    let mut sockets: Vec<SocketConfig> = Vec::new();
+    if let Some(entries) = cfg.get("socket").and_then(|v| v.as_array()) {
+        for (idx, entry) in entries.iter().enumerate() {
+            let table = locs.sockets.get(idx).unwrap_or(&locs.master);
+            let name = entry.get("name").and_then(|v| v.as_str())
+                .map(|n| format!("socket `{}`", n)).unwrap_or_else(|| "socket".to_owned());
+
+            if let Some(sock) = diag.load::<SocketConfig>(entry.clone(), table, &name) {
+                if sockets.iter().any(|s| s.name == sock.name) {
+                    diag.error(table.key("name"), format!("{}: duplicate socket name", name));
+                }
+                match sock.proto {
+                    Proto::unix => if sock.path.is_none() {
+                        diag.error(table.line, format!("{}: `path` is required", name));
+                    },
+                    _ => if sock.port.is_none() {
+                        diag.error(table.line, format!("{}: `port` is required", name));
+                    },
+                }
+                for srv in &sock.service {
+                    if !service_names.contains(&srv.as_str()) {
+                        diag.error(table.key("service"), format!(
+                            "{}: unknown service `{}`", name, srv));
+                    }
+                }
+                sockets.push(sock);
+            }
+        }
+    }
+
+    diag.errors.sort_by_key(|err| err.0);
+    diag.print();
+    if diag.errors.is_empty() {
+        println!("Configuration file {} is valid", path.display());
+        true
+    } else {
+        println!("{} error(s) found in {}", diag.errors.len(), path.display());
+        false
+    }
+}
+
+fn check_service(srv: &mut ServiceConfig, directory: &Path,
+                 table: &Table, name: &str, diag: &mut Diagnostics)
+{
+    if let Err(err) = srv.resolve(directory) {
+        diag.error(table.line, err);
+    }
+
+    // worker changes to service directory before command execution
+    let directory = match srv.directory {
+        Some(ref dir) => directory.join(dir),
+        None => directory.to_path_buf(),
+    };
+    if !directory.is_dir() {
+        diag.error(table.key("directory"), format!(
+            "{}: Directory {:?} does not exist", name, directory));
+    }
+
+    // command line errors are reported by `resolve`
+    if let Some(cmd) = srv.argv.first() {
+        let found = if cmd.contains('/') {
+            utils::find_path(directory.join(cmd).to_string_lossy().as_ref())
+        } else {
+            utils::find_path(cmd)
+        };
+        if found.is_none() {
+            let key = if srv.args.is_empty() { "command" } else { "args" };
+            diag.error(table.key(key), format!(
+                "{}: Can not find executable: {}", name, cmd));
+        }
+    }
+
+    for &(key, file) in &[("stdout", &srv.stdout), ("stderr", &srv.stderr)] {
+        if let Some(ref file) = *file {
+            if let Err(err) = check_writable(&directory.join(file)) {
+                diag.error(table.key(key), format!(
+                    "{}: Can not write {} file {:?}: {}", name, key, file, err));
+            }
+        }
+    }
+}
+
+/// Check that file can be opened for writing, or can be created
+fn check_writable(path: &Path) -> Result<(), std::io::Error> {
+    let (path, mode) = if path.exists() {
+        (path, libc::W_OK)
+    } else {
+        match path.parent() {
+            Some(parent) => (parent, libc::W_OK | libc::X_OK),
+            None => return Err(std::io::Error::from_raw_os_error(libc::ENOENT)),
+        }
+    };
+    let cpath = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| std::io::Error::from_raw_os_error(libc::EINVAL))?;
+
+    if unsafe { libc::access(cpath.as_ptr(), mode) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
@@ -1,11 +1,13 @@
 use std::ffi::CString;
 
 use libc;
+use nix::sys::signal::Signal;
 use nix::unistd::{Gid, Uid};
 use serde;
 use serde_json as json;
 
-use config::Proto;
+use config::{CpuAffinity, Proto, Protocol, ReloadBatch, RestartPolicy, StopSignal};
+use duration::parse_duration;
 
 
 pub fn default_vec<T>() -> Vec<T> {
@@ -24,10 +26,86 @@ pub fn default_proto() -> Proto {
     Proto::tcp4
 }
 
+pub fn default_protocol() -> Protocol {
+    Protocol::Fectl
+}
+
+pub fn default_stop_signals() -> Vec<StopSignal> {
+    vec![StopSignal { signal: Signal::SIGTERM, timeout: None }]
+}
+
+pub fn default_probe_host() -> String {
+    "127.0.0.1".to_owned()
+}
+
+pub fn default_probe_interval() -> u32 {
+    5
+}
+
+pub fn default_probe_timeout() -> u32 {
+    1
+}
+
+pub fn default_probe_threshold() -> u32 {
+    3
+}
+
 pub fn default_restarts() -> u16 {
     3
 }
 
+pub fn default_restart_policy() -> RestartPolicy {
+    RestartPolicy::Always
+}
+
+pub fn default_restart_window() -> u32 {
+    60
+}
+
+pub fn default_backoff_initial() -> f64 {
+    1.0
+}
+
+pub fn default_backoff_max() -> f64 {
+    60.0
+}
+
+pub fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+pub fn default_backoff_jitter() -> f64 {
+    0.1
+}
+
+pub fn default_recover_attempts() -> u16 {
+    5
+}
+
+pub fn default_revision_history() -> u16 {
+    5
+}
+
+pub fn default_min_workers() -> u16 {
+    1
+}
+
+pub fn default_target_utilization() -> u8 {
+    70
+}
+
+pub fn default_autoscale_window() -> u32 {
+    60
+}
+
+pub fn default_scale_up_cooldown() -> u32 {
+    60
+}
+
+pub fn default_scale_down_cooldown() -> u32 {
+    300
+}
+
 pub fn default_timeout() -> u32 {
     10
 }
@@ -40,35 +118,55 @@ pub fn default_shutdown_timeout() -> u32 {
     30
 }
 
+pub fn default_log_backups() -> u16 {
+    5
+}
+
 /// Deserialize `gid` field into `Gid`
 pub(crate) fn deserialize_gid_field<'de, D>(de: D) -> Result<Option<Gid>, D::Error>
     where D: serde::Deserializer<'de>
 {
     let deser_result: json::Value = serde::Deserialize::deserialize(de)?;
-    match deser_result {
+    gid_from_value(deser_result).map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Deserialize `groups` field, list of group names or group ids
+pub(crate) fn deserialize_groups_field<'de, D>(de: D) -> Result<Option<Vec<Gid>>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: Vec<json::Value> = serde::Deserialize::deserialize(de)?;
+    deser_result.into_iter()
+        .map(gid_from_value)
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn gid_from_value(value: json::Value) -> Result<Gid, &'static str> {
+    match value {
         json::Value::String(ref s) =>
             if let Ok(name) = CString::new(s.as_str()) {
                 unsafe {
                     let ptr = libc::getgrnam(name.as_ptr());
                     return if ptr.is_null() {
-                        Err(serde::de::Error::custom("Can not convert group name to group id"))
+                        Err("Can not convert group name to group id")
                     } else {
-                        Ok(Some(Gid::from_raw((*ptr).gr_gid)))
+                        Ok(Gid::from_raw((*ptr).gr_gid))
                     };
                 }
             } else {
-                return Err(serde::de::Error::custom("Can not convert to plain string"))
+                return Err("Can not convert to plain string")
             }
         json::Value::Number(num) => {
             if let Some(num) = num.as_u64() {
                 if num <= u64::from(u32::max_value()) {
-                    return Ok(Some(Gid::from_raw(num as libc::gid_t)))
+                    return Ok(Gid::from_raw(num as libc::gid_t))
                 }
             }
         }
         _ => (),
     }
-    Err(serde::de::Error::custom("Unexpected value"))
+    Err("Unexpected value")
 }
 
 /// Deserialize `uid` field into `Uid`
@@ -101,3 +199,226 @@ pub fn deserialize_uid_field<'de, D>(de: D) -> Result<Option<Uid>, D::Error>
     }
     Err(serde::de::Error::custom("Unexpected value"))
 }
+
+/// Deserialize file `mode` field, octal string or integer
+pub fn deserialize_mode_field<'de, D>(de: D) -> Result<Option<u32>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: json::Value = serde::Deserialize::deserialize(de)?;
+    match deser_result {
+        json::Value::String(ref s) =>
+            if let Ok(mode) = u32::from_str_radix(s.trim_start_matches("0o"), 8) {
+                if mode <= 0o7777 {
+                    return Ok(Some(mode))
+                }
+            },
+        json::Value::Number(num) => {
+            if let Some(num) = num.as_u64() {
+                if num <= 0o7777 {
+                    return Ok(Some(num as u32))
+                }
+            }
+        }
+        _ => (),
+    }
+    Err(serde::de::Error::custom("Can not convert to file mode"))
+}
+
+/// Deserialize size in bytes, integer or string with `K`, `M`, `G` suffix
+pub fn deserialize_size_field<'de, D>(de: D) -> Result<Option<u64>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: json::Value = serde::Deserialize::deserialize(de)?;
+    match deser_result {
+        json::Value::String(ref s) => {
+            let s = s.trim();
+            let (num, mult) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+                Some('K') => (&s[..s.len()-1], 1 << 10),
+                Some('M') => (&s[..s.len()-1], 1 << 20),
+                Some('G') => (&s[..s.len()-1], 1 << 30),
+                _ => (s, 1),
+            };
+            if let Ok(num) = num.trim().parse::<u64>() {
+                if let Some(size) = num.checked_mul(mult) {
+                    return Ok(Some(size))
+                }
+            }
+        }
+        json::Value::Number(num) => {
+            if let Some(num) = num.as_u64() {
+                return Ok(Some(num))
+            }
+        }
+        _ => (),
+    }
+    Err(serde::de::Error::custom("Can not convert to size"))
+}
+
+/// Deserialize `cpu_max` field, percent of one cpu or "quota period" in microseconds
+pub fn deserialize_cpu_max_field<'de, D>(de: D) -> Result<Option<String>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: String = serde::Deserialize::deserialize(de)?;
+    let value = deser_result.trim();
+    if let Some(percent) = value.strip_suffix('%') {
+        if let Ok(percent) = percent.trim().parse::<u64>() {
+            if percent > 0 {
+                return Ok(Some(format!("{} 100000", percent * 1000)))
+            }
+        }
+    } else {
+        let parts: Vec<_> = value.split_whitespace().collect();
+        let valid = match parts.len() {
+            1 => parts[0] == "max" || parts[0].parse::<u64>().is_ok(),
+            2 => (parts[0] == "max" || parts[0].parse::<u64>().is_ok()) &&
+                parts[1].parse::<u64>().is_ok(),
+            _ => false,
+        };
+        if valid {
+            return Ok(Some(parts.join(" ")))
+        }
+    }
+    Err(serde::de::Error::custom("Expected percent or \"quota period\" value"))
+}
+
+/// Deserialize resource limit, integer or "unlimited"
+pub fn deserialize_rlimit_field<'de, D>(de: D) -> Result<Option<u64>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: json::Value = serde::Deserialize::deserialize(de)?;
+    match deser_result {
+        json::Value::String(ref s) if s == "unlimited" =>
+            return Ok(Some(libc::RLIM_INFINITY)),
+        json::Value::Number(num) => {
+            if let Some(num) = num.as_u64() {
+                return Ok(Some(num))
+            }
+        }
+        _ => (),
+    }
+    Err(serde::de::Error::custom("Expected integer or \"unlimited\""))
+}
+
+/// Deserialize `cpu_affinity` field, list of cpus or "worker"
+pub fn deserialize_cpu_affinity_field<'de, D>(de: D) -> Result<Option<CpuAffinity>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: json::Value = serde::Deserialize::deserialize(de)?;
+    match deser_result {
+        json::Value::String(ref s) if s == "worker" =>
+            return Ok(Some(CpuAffinity::Worker)),
+        json::Value::Array(items) => {
+            let cpus: Vec<usize> = items.iter()
+                .filter_map(|item| item.as_u64().map(|cpu| cpu as usize)).collect();
+            if cpus.len() == items.len() && !cpus.is_empty() {
+                return Ok(Some(CpuAffinity::Cpus(cpus)))
+            }
+        }
+        _ => (),
+    }
+    Err(serde::de::Error::custom("Expected list of cpu numbers or \"worker\""))
+}
+
+/// Deserialize duration in seconds, integer or string with `s`, `m`, `h`, `d` suffix
+pub fn deserialize_duration_field<'de, D>(de: D) -> Result<Option<u64>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: json::Value = serde::Deserialize::deserialize(de)?;
+    match deser_result {
+        json::Value::String(ref s) =>
+            if let Some(secs) = parse_duration(s) {
+                return Ok(Some(secs))
+            },
+        json::Value::Number(num) => {
+            if let Some(num) = num.as_u64() {
+                return Ok(Some(num))
+            }
+        }
+        _ => (),
+    }
+    Err(serde::de::Error::custom("Can not convert to duration"))
+}
+
+/// Deserialize `reload_batch` field, number of workers or percent string
+pub fn deserialize_reload_batch_field<'de, D>(de: D) -> Result<Option<ReloadBatch>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: json::Value = serde::Deserialize::deserialize(de)?;
+    match deser_result {
+        json::Value::String(ref s) =>
+            if let Some(percent) = s.trim().strip_suffix('%') {
+                if let Ok(percent) = percent.trim().parse::<u8>() {
+                    if percent > 0 && percent <= 100 {
+                        return Ok(Some(ReloadBatch::Percent(percent)))
+                    }
+                }
+            },
+        json::Value::Number(num) => {
+            if let Some(num) = num.as_u64() {
+                if num > 0 {
+                    return Ok(Some(ReloadBatch::Count(num as usize)))
+                }
+            }
+        }
+        _ => (),
+    }
+    Err(serde::de::Error::custom("Expected number of workers or percent"))
+}
+
+/// Deserialize `stop_signals` field, list of "SIGNAL" or "SIGNAL:timeout" values
+pub fn deserialize_stop_signals_field<'de, D>(de: D) -> Result<Vec<StopSignal>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    let deser_result: Vec<String> = serde::Deserialize::deserialize(de)?;
+    if deser_result.is_empty() {
+        return Err(serde::de::Error::custom("Stop signals list is empty"))
+    }
+    deser_result.iter()
+        .map(|item| {
+            let mut parts = item.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim().to_uppercase();
+            let signal = Signal::iterator()
+                .find(|sig| format!("{:?}", sig) == name ||
+                      format!("{:?}", sig) == format!("SIG{}", name))
+                .ok_or_else(|| format!("Unknown signal: {:?}", item))?;
+            let timeout = match parts.next() {
+                Some(timeout) => Some(parse_duration(timeout).ok_or_else(
+                    || format!("Can not convert to duration: {:?}", item))?),
+                None => None,
+            };
+            Ok(StopSignal { signal, timeout })
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    #[derive(Deserialize)]
+    struct Batch {
+        #[serde(default)]
+        #[serde(deserialize_with="deserialize_reload_batch_field")]
+        reload_batch: Option<ReloadBatch>,
+    }
+
+    fn batch(value: &str) -> Result<Option<ReloadBatch>, toml::de::Error> {
+        toml::from_str::<Batch>(&format!("reload_batch = {}", value))
+            .map(|batch| batch.reload_batch)
+    }
+
+    #[test]
+    fn test_reload_batch_field() {
+        assert_eq!(batch("2").unwrap(), Some(ReloadBatch::Count(2)));
+        assert_eq!(batch("\"25%\"").unwrap(), Some(ReloadBatch::Percent(25)));
+        assert_eq!(batch("\" 100 % \"").unwrap(), Some(ReloadBatch::Percent(100)));
+
+        assert!(batch("0").is_err());
+        assert!(batch("\"0%\"").is_err());
+        assert!(batch("\"101%\"").is_err());
+        assert!(batch("\"25\"").is_err());
+        assert!(batch("-1").is_err());
+    }
+}
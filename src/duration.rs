@@ -0,0 +1,30 @@
+// Duration settings, shared with `fectl` client
+
+/// Parse duration in seconds, number or with `s`, `m`, `h`, `d` suffix
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, mult) = match s.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('s') => (&s[..s.len()-1], 1),
+        Some('m') => (&s[..s.len()-1], 60),
+        Some('h') => (&s[..s.len()-1], 3600),
+        Some('d') => (&s[..s.len()-1], 86_400),
+        _ => (s, 1),
+    };
+    num.trim().parse::<u64>().ok().and_then(|num| num.checked_mul(mult))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("30s"), Some(30));
+        assert_eq!(parse_duration("2M"), Some(120));
+        assert_eq!(parse_duration(" 1h "), Some(3600));
+        assert_eq!(parse_duration("1d"), Some(86_400));
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration("-1s"), None);
+    }
+}
@@ -0,0 +1,144 @@
+use std;
+use std::env;
+use std::path::Path;
+use std::io::prelude::*;
+
+use config::ServiceConfig;
+
+/// Variables with these markers in the name are not shown in status output
+const SECRET_MARKERS: &[&str] = &[
+    "SECRET", "PASSWORD", "PASSWD", "TOKEN", "CREDENTIAL", "PRIVATE", "API_KEY", "ACCESS_KEY"];
+
+const REDACTED: &str = "******";
+
+
+/// Build service environment from `env_file` and `environ` entries.
+///
+/// `env_file` is loaded first, `environ` entries override values from the file.
+/// `${VAR}` references are expanded with previously defined service variables
+/// or with variables from master process environment.
+pub fn load(cfg: &ServiceConfig, directory: &Path) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+
+    if let Some(ref env_file) = cfg.env_file {
+        let path = directory.join(env_file);
+        let mut content = String::new();
+        if let Err(err) = std::fs::File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+        {
+            return Err(format!("Can not read env file {:?}: {}", path, err))
+        }
+
+        for (num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let line = match line.strip_prefix("export ") {
+                Some(line) => line.trim(),
+                None => line,
+            };
+            match parse_entry(line, &vars, true) {
+                Ok(entry) => set(&mut vars, entry),
+                Err(err) => return Err(format!("{:?}, line {}: {}", path, num + 1, err)),
+            }
+        }
+    }
+
+    for entry in &cfg.environ {
+        match parse_entry(entry, &vars, false) {
+            Ok(entry) => set(&mut vars, entry),
+            Err(err) => return Err(format!("`environ` entry {:?}: {}", entry, err)),
+        }
+    }
+    Ok(vars)
+}
+
+/// Replace values of secret variables
+pub fn redact(vars: &[(String, String)]) -> Vec<(String, String)> {
+    vars.iter().map(|var| {
+        let upper = var.0.to_uppercase();
+        if SECRET_MARKERS.iter().any(|m| upper.contains(m)) {
+            (var.0.clone(), REDACTED.to_owned())
+        } else {
+            var.clone()
+        }
+    }).collect()
+}
+
+fn set(vars: &mut Vec<(String, String)>, entry: (String, String)) {
+    vars.retain(|var| var.0 != entry.0);
+    vars.push(entry);
+}
+
+fn parse_entry(entry: &str, vars: &[(String, String)], quoted: bool)
+               -> Result<(String, String), String>
+{
+    let pos = match entry.find('=') {
+        Some(pos) => pos,
+        None => return Err("expected KEY=VALUE".to_owned()),
+    };
+    let key = entry[..pos].trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid variable name {:?}", key))
+    }
+
+    let mut value = &entry[pos+1..];
+    if quoted {
+        value = value.trim();
+    }
+    let value = if quoted && value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        // single quoted values are taken literally
+        value[1..value.len()-1].to_owned()
+    } else if quoted && value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        expand(&unescape(&value[1..value.len()-1]), vars)?
+    } else {
+        expand(value, vars)?
+    };
+    if value.contains('\0') {
+        return Err("value contains nul byte".to_owned())
+    }
+    Ok((key.to_owned(), value))
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(ch) => result.push(ch),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+/// Expand `${VAR}` references
+fn expand(value: &str, vars: &[(String, String)]) -> Result<String, String> {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(pos) = rest.find("${") {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos+2..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => return Err("unterminated variable reference".to_owned()),
+        };
+        let name = &rest[..end];
+        if let Some(var) = vars.iter().find(|var| var.0 == name) {
+            result.push_str(&var.1);
+        } else if let Ok(val) = env::var(name) {
+            result.push_str(&val);
+        }
+        rest = &rest[end+1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
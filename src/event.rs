@@ -2,7 +2,144 @@ use std;
 use std::collections::VecDeque;
 use std::time::{UNIX_EPOCH, SystemTime};
 
-pub type ServiceStatus = (String, Vec<(String, Vec<Event>)>);
+/// Service status
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServiceStatus {
+    /// Service state
+    pub status: String,
+    /// Worker events
+    pub workers: Vec<(String, Vec<Event>)>,
+    /// Service environment, secret values are redacted
+    #[serde(default)]
+    pub environ: Vec<(String, String)>,
+    /// Resource usage of service cgroup
+    #[serde(default)]
+    pub usage: Option<ResourceUsage>,
+    /// Resource usage of running worker processes
+    #[serde(default)]
+    pub stats: Vec<ProcessStats>,
+    /// Sum of worker processes usage, `worker` and `pid` are not set
+    #[serde(default)]
+    pub total: Option<ProcessStats>,
+    /// Remembered configuration revisions, current revision is last
+    #[serde(default)]
+    pub revisions: Vec<RevisionInfo>,
+    /// Recent changes of number of workers
+    #[serde(default)]
+    pub scaling: Vec<ScalingEvent>,
+}
+
+/// Change of number of service workers
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ScalingEvent {
+    pub timestamp: u64,
+    /// Number of workers before change
+    pub from: usize,
+    /// Number of workers after change
+    pub to: usize,
+    pub reason: Reason,
+}
+
+/// Service configuration revision
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevisionInfo {
+    pub id: u32,
+    pub timestamp: u64,
+    /// Changed settings
+    pub description: String,
+}
+
+/// Resource usage of worker process, read from `/proc`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ProcessStats {
+    /// Worker number, starting from 1
+    pub worker: usize,
+    pub pid: String,
+    /// Resident set size in bytes
+    pub rss: u64,
+    /// Cpu usage in percents of one cpu
+    pub cpu: f64,
+    pub threads: u64,
+    pub fds: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    /// Process uptime in seconds
+    pub uptime: u64,
+    /// Number of worker restarts
+    pub restarts: u64,
+    /// Load reported in worker heartbeat
+    #[serde(default)]
+    pub load: Option<Load>,
+}
+
+/// Worker load, reported in heartbeat message
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Load {
+    /// Number of requests in process
+    #[serde(default)]
+    pub inflight: u64,
+    /// Number of requests waiting for processing
+    #[serde(default)]
+    pub queue: u64,
+}
+
+impl Load {
+    pub fn total(&self) -> u64 {
+        self.inflight + self.queue
+    }
+}
+
+impl ProcessStats {
+
+    /// Add usage of other process
+    pub fn add(&mut self, other: &ProcessStats) {
+        self.rss += other.rss;
+        self.cpu += other.cpu;
+        self.threads += other.threads;
+        self.fds += other.fds;
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+        self.restarts += other.restarts;
+        if let Some(load) = other.load {
+            let total = self.load.get_or_insert_with(Load::default);
+            total.inflight += load.inflight;
+            total.queue += load.queue;
+        }
+    }
+}
+
+/// Aggregated resource usage of service workers
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResourceUsage {
+    /// Memory usage in bytes
+    pub memory: u64,
+    /// Memory limit in bytes
+    pub memory_max: Option<u64>,
+    /// Total cpu time in microseconds
+    pub cpu_usec: u64,
+    /// Number of processes
+    pub pids: u64,
+    /// Number of processes killed by OOM killer
+    pub oom_kills: u64,
+}
+
+/// Worker output stream
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Line of worker output
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct LogLine {
+    /// Worker number, starting from 1
+    pub worker: usize,
+    pub pid: String,
+    pub stream: OutputStream,
+    pub timestamp: u64,
+    pub line: String,
+}
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
 pub enum State {
@@ -17,6 +154,9 @@ pub enum State {
     Paused,
     RestartFailed,
     ReloadFailed,
+    Backoff,
+    /// New process is loaded and observed, old process keeps running
+    Canary,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -41,6 +181,20 @@ pub enum Reason {
     RestartFailedRunningWorker,
     RestoreAftreFailed,
     ReloadAftreTimeout,
+    ConfigReload,
+    OutOfMemory,
+    TooManyRestarts,
+    AutoRecovery,
+    ProbeFailed(String),
+    MemoryLimit,
+    CpuLimit,
+    LifetimeExpired,
+    CanaryFailed,
+    Deploy,
+    Rollback,
+    Scale,
+    /// Autoscaler decision
+    Autoscale(String),
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -49,6 +203,24 @@ pub struct Event {
     pub reason: Reason,
     pub timestamp: u64,
     pub pid: Option<String>,
+    /// Last stderr lines of failed process
+    #[serde(default)]
+    pub stderr: Option<Vec<String>>,
+    /// Restart delay of worker in `Backoff` state
+    #[serde(default)]
+    pub backoff: Option<Backoff>,
+    /// Configuration revision of the process
+    #[serde(default)]
+    pub revision: Option<u32>,
+}
+
+/// Restart delay
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Backoff {
+    /// Delay in milliseconds
+    pub delay: u64,
+    /// Time of next restart attempt
+    pub retry_at: u64,
 }
 
 impl Event {
@@ -58,6 +230,9 @@ impl Event {
             state,
             reason,
             pid,
+            stderr: None,
+            backoff: None,
+            revision: None,
             timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
         }
     }
@@ -82,6 +257,16 @@ impl Events {
         }
         self.events.push_back(Event::new(state, reason, pid));
     }
+
+    /// Latest event
+    pub fn last_mut(&mut self) -> Option<&mut Event> {
+        self.events.back_mut()
+    }
+
+    /// Latest event of the process
+    pub fn last_of(&mut self, pid: &str) -> Option<&mut Event> {
+        self.events.iter_mut().rev().find(|ev| ev.pid.as_deref() == Some(pid))
+    }
 }
 
 
@@ -2,17 +2,20 @@
 use std;
 use std::ffi::CString;
 use std::io::{Read, Write};
-use std::os::unix::io::{RawFd, AsRawFd, FromRawFd};
+use std::os::unix::io::{RawFd, FromRawFd, IntoRawFd};
 
 use libc;
 use bytes::{BytesMut, Buf, BufMut, IntoBuf};
 use byteorder::BigEndian;
 use serde_json as json;
-use nix::unistd::{chdir, dup2, execve, setuid, setgid};
+use nix::unistd::{chdir, close, dup2, execve};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, FD_CLOEXEC};
 
 use utils;
+use limits;
 use worker::{WorkerCommand, WorkerMessage};
-use config::ServiceConfig;
+use config::{Protocol, ServiceConfig};
+use privileges::Privileges;
 use process::{WORKER_INIT_FAILED, WORKER_BOOT_FAILED};
 
 
@@ -29,31 +32,20 @@ fn send_msg(file: &mut std::fs::File, msg: WorkerMessage) {
     }
 }
 
-pub fn exec_worker(idx: usize, cfg: &ServiceConfig, read: RawFd, write: RawFd) {
-    // notify master
-    let mut file = unsafe{ std::fs::File::from_raw_fd(write) };
-    send_msg(&mut file, WorkerMessage::forked);
-
-    // read master response
-    let mut buffer = [0; 2];
-    let mut file = unsafe{ std::fs::File::from_raw_fd(read) };
-    if let Err(err) = file.read_exact(&mut buffer) {
-        error!("Failed to read master response: {}", err);
-        std::process::exit(WORKER_INIT_FAILED as i32);
-    }
-    let size = buffer.into_buf().get_u16::<BigEndian>();
-    let mut buffer = Vec::with_capacity(size as usize);
-    unsafe {buffer.set_len(size as usize)};
-    if let Err(err) = file.read_exact(&mut buffer) {
-        error!("Failed to read master response: {}", err);
+pub fn exec_worker(idx: usize, cfg: &ServiceConfig,
+                   read: RawFd, write: RawFd, output: (RawFd, RawFd)) {
+    // redirect stdout and stderr to master's pipes
+    if dup2(output.0, libc::STDOUT_FILENO).is_err() ||
+        dup2(output.1, libc::STDERR_FILENO).is_err()
+    {
         std::process::exit(WORKER_INIT_FAILED as i32);
     }
-    match json::from_slice::<WorkerCommand>(&buffer) {
-        Ok(WorkerCommand::prepare) => (),
-        Ok(_) | Err(_) => {
-            error!("Can not decode master's message: {:?}", &buffer);
-            std::process::exit(WORKER_INIT_FAILED as i32);
-        }
+    let _ = close(output.0);
+    let _ = close(output.1);
+
+    let mut file = unsafe{ std::fs::File::from_raw_fd(write) };
+    if cfg.protocol == Protocol::Fectl {
+        handshake(&mut file, read);
     }
 
     // change dir
@@ -66,26 +58,27 @@ pub fn exec_worker(idx: usize, cfg: &ServiceConfig, read: RawFd, write: RawFd) {
         }
     }
 
-    // set uid
-    if let Some(uid) = cfg.uid {
-        if let Err(err) = setuid(uid) {
-            send_msg(&mut file, WorkerMessage::cfgerror(
-                format!("Can not set worker uid, err: {}", err)));
-            std::process::exit(WORKER_INIT_FAILED as i32);
-        }
+    // resource limits, require privileges
+    if let Err(err) = limits::apply(idx, cfg) {
+        send_msg(&mut file, WorkerMessage::cfgerror(err));
+        std::process::exit(WORKER_INIT_FAILED as i32);
     }
 
-    // set gid
-    if let Some(gid) = cfg.gid {
-        if let Err(err) = setgid(gid) {
-            send_msg(&mut file, WorkerMessage::cfgerror(
-                format!("Can not set worker gid, err: {}", err)));
-            std::process::exit(WORKER_INIT_FAILED as i32);
-        }
+    // set groups, gid and uid
+    let privileges = Privileges {
+        uid: cfg.uid,
+        gid: cfg.gid,
+        groups: cfg.groups.as_deref(),
+        capabilities: &cfg.capabilities,
+        no_new_privs: cfg.no_new_privs,
+    };
+    if let Err(err) = privileges.apply() {
+        send_msg(&mut file, WorkerMessage::cfgerror(format!("Worker {}", err)));
+        std::process::exit(WORKER_INIT_FAILED as i32);
     }
 
     // prepare command and arguments
-    let mut iter = cfg.command.split_whitespace();
+    let mut iter = cfg.argv.iter();
     let path = if let Some(path) = iter.next() {
         if let Some(path) = utils::find_path(path) {
             path
@@ -102,49 +95,115 @@ pub fn exec_worker(idx: usize, cfg: &ServiceConfig, read: RawFd, write: RawFd) {
         std::process::exit(WORKER_INIT_FAILED as i32);
     };
     let mut args: Vec<_> = vec![CString::new(path.as_str()).unwrap()];
-    args.extend(iter.map(|s| CString::new(s).unwrap()).collect::<Vec<_>>());
-
-    // redirect stdout and stderr
-    if let Some(ref stdout) = cfg.stdout {
-        match std::fs::OpenOptions::new().append(true).create(true).open(stdout)
-        {
-            Ok(f) => {
-            let _ = dup2(f.as_raw_fd(), libc::STDOUT_FILENO);
-        }
-            Err(err) => {
-                send_msg(&mut file, WorkerMessage::cfgerror(
-                    format!("Can open stdout file {}: {}", stdout, err)));
-                std::process::exit(WORKER_INIT_FAILED as i32);
-            }
-        }
+    // nul bytes are rejected during configuration loading
+    for arg in iter {
+        args.push(CString::new(arg.as_str()).unwrap());
     }
 
-    if let Some(ref stderr) = cfg.stderr {
-        match std::fs::OpenOptions::new().append(true).create(true).open(stderr)
-        {
-            Ok(f) => {
-                let _ = dup2(f.as_raw_fd(), libc::STDERR_FILENO);
+    debug!("Starting worker: {:?}", cfg.argv);
 
-            },
-            Err(err) => {
-                send_msg(&mut file, WorkerMessage::cfgerror(
-                    format!("Can open stderr file {}: {}", stderr, err)));
-                std::process::exit(WORKER_INIT_FAILED as i32);
+    let mut env = utils::get_env_vars(true);
+    for (key, val) in &cfg.env {
+        let prefix = format!("{}=", key);
+        env.retain(|v| !v.as_bytes().starts_with(prefix.as_bytes()));
+        env.push(CString::new(format!("{}={}", key, val)).unwrap());
+    }
+
+    // pass service listeners, all other listeners are close-on-exec
+    for sock in &cfg.sockets {
+        if let Err(err) = fcntl(sock.fd, FcntlArg::F_SETFD(FdFlag::empty())) {
+            send_msg(&mut file, WorkerMessage::cfgerror(
+                format!("Can not pass listener fd {}: {}", sock.fd, err)));
+            std::process::exit(WORKER_INIT_FAILED as i32);
+        }
+        for (key, val) in &sock.env {
+            env.push(CString::new(format!("{}={}", key, val)).unwrap());
+        }
+    }
+    // descriptors opened by master are close-on-exec too
+    for fd in &cfg.inherit_fds {
+        if let Err(err) = fcntl(*fd, FcntlArg::F_SETFD(FdFlag::empty())) {
+            send_msg(&mut file, WorkerMessage::cfgerror(
+                format!("Can not pass inherited fd {}: {}", fd, err)));
+            std::process::exit(WORKER_INIT_FAILED as i32);
+        }
+    }
+    // close file descriptors inherited from master process
+    let mut keep = vec![libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO, read, write];
+    keep.extend(cfg.sockets.iter().map(|sock| sock.fd));
+    keep.extend(cfg.inherit_fds.iter());
+    close_fds(&keep);
+
+    match cfg.protocol {
+        Protocol::Fectl =>
+            env.push(CString::new(format!("FECTL_FD={}:{}", read, write)).unwrap()),
+        Protocol::None => {
+            // master treats closed pipe as successful exec
+            for fd in &[read, write] {
+                let _ = fcntl(*fd, FcntlArg::F_SETFD(FD_CLOEXEC));
             }
         }
     }
-
-    debug!("Starting worker: {:?}", cfg.command);
-
-    let mut env = utils::get_env_vars(true);
-    env.push(CString::new(format!("FECTL_FD={}:{}", read, write)).unwrap());
     env.push(CString::new(format!("FECTL_SRV_NAME={}", cfg.name)).unwrap());
     env.push(CString::new(format!("FECTL_PROC_IDX={}", idx)).unwrap());
     match execve(&CString::new(path).unwrap(), &args, &env) {
         Ok(_) => unreachable!(),
         Err(err) => {
-            error!("Can not execute command: \"{}\" with error: {:?}", cfg.command, err);
+            error!("Can not execute command: {:?} with error: {:?}", cfg.argv, err);
+            if cfg.protocol == Protocol::None {
+                send_msg(&mut file, WorkerMessage::cfgerror(
+                    format!("Can not execute command: {}", err)));
+            }
             std::process::exit(WORKER_BOOT_FAILED as i32);
         }
     }
 }
+
+/// Notify master and wait for `prepare` command
+fn handshake(file: &mut std::fs::File, read: RawFd) {
+    send_msg(file, WorkerMessage::forked);
+
+    // read master response
+    let mut buffer = [0; 2];
+    let mut reader = unsafe{ std::fs::File::from_raw_fd(read) };
+    if let Err(err) = reader.read_exact(&mut buffer) {
+        error!("Failed to read master response: {}", err);
+        std::process::exit(WORKER_INIT_FAILED as i32);
+    }
+    let size = buffer.into_buf().get_u16::<BigEndian>();
+    let mut buffer = Vec::with_capacity(size as usize);
+    unsafe {buffer.set_len(size as usize)};
+    if let Err(err) = reader.read_exact(&mut buffer) {
+        error!("Failed to read master response: {}", err);
+        std::process::exit(WORKER_INIT_FAILED as i32);
+    }
+    match json::from_slice::<WorkerCommand>(&buffer) {
+        Ok(WorkerCommand::prepare) => (),
+        Ok(_) | Err(_) => {
+            error!("Can not decode master's message: {:?}", &buffer);
+            std::process::exit(WORKER_INIT_FAILED as i32);
+        }
+    }
+    // descriptor stays open for the worker
+    let _ = reader.into_raw_fd();
+}
+
+/// Close all open file descriptors except `keep`
+fn close_fds(keep: &[RawFd]) {
+    let fds: Vec<RawFd> = match std::fs::read_dir("/proc/self/fd") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().and_then(|s| s.parse().ok()))
+            .collect(),
+        Err(err) => {
+            error!("Can not list open file descriptors: {}", err);
+            return
+        }
+    };
+    // descriptor of closed `/proc/self/fd` directory is in the list as well
+    for fd in fds {
+        if !keep.contains(&fd) {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
@@ -12,18 +12,14 @@ use nix::fcntl::{fcntl, FcntlArg, OFlag, O_NONBLOCK};
 
 
 pub struct PipeFile {
-    read: Io,
     read_poll: PollEvented<Io>,
-    write: Io,
     write_poll: PollEvented<Io>,
 }
 
 impl PipeFile {
     pub fn new(read: RawFd, write: RawFd, handle: &Handle) -> PipeFile {
         PipeFile {
-            read: unsafe{ Io::from_raw_fd(read) },
             read_poll: PollEvented::new(unsafe{ Io::from_raw_fd(read) }, handle).unwrap(),
-            write: unsafe{ Io::from_raw_fd(write) },
             write_poll: PollEvented::new(unsafe{ Io::from_raw_fd(write) }, handle).unwrap(),
         }
     }
@@ -32,7 +28,7 @@ impl PipeFile {
 impl Read for PipeFile {
     fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
         match self.read_poll.poll_read() {
-            Async::Ready(_) => match self.read.read(dst) {
+            Async::Ready(_) => match self.read_poll.get_ref().read(dst) {
                 Ok(size) => {
                     self.read_poll.need_read();
                     Ok(size)
@@ -47,9 +43,9 @@ impl Read for PipeFile {
 impl Write for PipeFile {
     fn write(&mut self, src: &[u8]) -> io::Result<usize> {
         match self.write_poll.poll_write() {
-            Async::Ready(_) => match self.write.write(src) {
+            Async::Ready(_) => match self.write_poll.get_ref().write(src) {
                 Ok(size) => {
-                    self.read_poll.need_write();
+                    self.write_poll.need_write();
                     Ok(size)
                 },
                 Err(err) => Err(err)
@@ -59,7 +55,7 @@ impl Write for PipeFile {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        (&self.write).flush()
+        self.write_poll.get_ref().flush()
     }
 }
 
@@ -0,0 +1,91 @@
+// Worker process resource limits
+use std;
+use std::io::Write;
+
+use libc;
+use nix::sys::stat::{umask, Mode};
+
+use config::{CpuAffinity, ServiceConfig};
+
+
+/// Apply resource limits of the service to current process.
+///
+/// Should be called before privileges get dropped, raising hard limits,
+/// lowering nice value and oom score require root.
+pub fn apply(idx: usize, cfg: &ServiceConfig) -> Result<(), String> {
+    let rlimits = [
+        ("rlimit_nofile", libc::RLIMIT_NOFILE, cfg.rlimit_nofile),
+        ("rlimit_core", libc::RLIMIT_CORE, cfg.rlimit_core),
+        ("rlimit_as", libc::RLIMIT_AS, cfg.rlimit_as),
+        ("rlimit_nproc", libc::RLIMIT_NPROC, cfg.rlimit_nproc)];
+    for &(name, resource, limit) in &rlimits {
+        if let Some(limit) = limit {
+            let rlim = libc::rlimit { rlim_cur: limit, rlim_max: limit };
+            if unsafe { libc::setrlimit(resource, &rlim) } != 0 {
+                return Err(format!("Can not set {} to {}: {}",
+                                   name, limit, std::io::Error::last_os_error()))
+            }
+        }
+    }
+
+    if let Some(mode) = cfg.umask {
+        umask(Mode::from_bits_truncate(mode));
+    }
+
+    if let Some(nice) = cfg.nice {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS as libc::__priority_which_t, 0, nice) } != 0 {
+            return Err(format!("Can not set nice value to {}: {}",
+                               nice, std::io::Error::last_os_error()))
+        }
+    }
+
+    if let Some(score) = cfg.oom_score_adj {
+        if let Err(err) = std::fs::OpenOptions::new().write(true)
+            .open("/proc/self/oom_score_adj")
+            .and_then(|mut f| f.write_all(score.to_string().as_bytes()))
+        {
+            return Err(format!("Can not set oom_score_adj to {}: {}", score, err))
+        }
+    }
+
+    if let Some(ref affinity) = cfg.cpu_affinity {
+        set_cpu_affinity(idx, affinity)?;
+    }
+    Ok(())
+}
+
+fn set_cpu_affinity(idx: usize, affinity: &CpuAffinity) -> Result<(), String> {
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+
+    match *affinity {
+        CpuAffinity::Worker => {
+            // pin worker to one of cpus available to master process
+            if unsafe { libc::sched_getaffinity(0, size, &mut set) } != 0 {
+                return Err(format!("Can not get cpu affinity: {}",
+                                   std::io::Error::last_os_error()))
+            }
+            let cpus: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+                .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) })
+                .collect();
+            if cpus.is_empty() {
+                return Ok(())
+            }
+            set = unsafe { std::mem::zeroed() };
+            unsafe { libc::CPU_SET(cpus[idx % cpus.len()], &mut set) };
+        }
+        CpuAffinity::Cpus(ref cpus) => {
+            for cpu in cpus {
+                if *cpu >= libc::CPU_SETSIZE as usize {
+                    return Err(format!("Cpu number is out of range: {}", cpu))
+                }
+                unsafe { libc::CPU_SET(*cpu, &mut set) };
+            }
+        }
+    }
+
+    if unsafe { libc::sched_setaffinity(0, size, &set) } != 0 {
+        return Err(format!("Can not set cpu affinity: {}", std::io::Error::last_os_error()))
+    }
+    Ok(())
+}
@@ -20,6 +20,7 @@ extern crate futures;
 extern crate tokio_core;
 extern crate tokio_uds;
 extern crate tokio_io;
+extern crate tokio_signal;
 
 #[macro_use]
 extern crate actix;
@@ -27,18 +28,27 @@ extern crate actix;
 mod addrinfo;
 mod client;
 mod config;
+mod config_check;
 mod config_helpers;
+mod duration;
+mod cgroup;
 mod cmd;
 mod exec;
+mod environ;
 mod event;
 mod logging;
 mod master;
 mod master_types;
+mod output;
+mod probe;
+mod procstat;
 mod service;
 mod socket;
 mod worker;
+mod privileges;
 mod process;
 mod io;
+mod limits;
 mod utils;
 
 mod version {
@@ -46,8 +56,14 @@ mod version {
 }
 
 fn main() {
+    let args = <config::Cli as structopt::StructOpt>::from_args();
+    if args.check_config {
+        let code = if config_check::check(&args.config) { 0 } else { 1 };
+        std::process::exit(code);
+    }
+
     let sys = actix::System::new("fectl");
-    let loaded = match config::load_config() {
+    let loaded = match config::load_config(args) {
         Some(cfg) => master::start(cfg),
         None => false,
     };
@@ -1,6 +1,5 @@
 use std;
 use std::io;
-use std::rc::Rc;
 use std::ffi::OsStr;
 use std::time::Duration;
 use std::thread;
@@ -23,14 +22,19 @@ use actix::prelude::*;
 
 use client;
 use logging;
-use config::Config;
+use config::{Config, MasterConfig};
+use privileges::Privileges;
 use version::PKG_INFO;
 use cmd::{self, CommandCenter, CommandError};
-use service::{StartStatus, ReloadStatus, ServiceOperationError};
-use master_types::{MasterRequest, MasterResponse};
+use event::LogLine;
+use service::{Canary, LogLines, StartStatus, ReloadStatus, ServiceOperationError};
+use master_types::{LogsRequest, MasterRequest, MasterResponse};
+
+/// Maximum size of log lines in one response, response has to fit into protocol frame
+const MAX_LOG_LINES_SIZE: usize = 32_768;
 
 pub struct Master {
-    cfg: Rc<Config>,
+    cfg: MasterConfig,
     cmd: Addr<Unsync, CommandCenter>,
 }
 
@@ -59,7 +63,7 @@ impl StreamHandler<NetStream, io::Error> for Master {
 
 impl Drop for Master {
     fn drop(&mut self) {
-        self.cfg.master.remove_files();
+        self.cfg.remove_files();
     }
 }
 
@@ -105,8 +109,12 @@ impl MasterClient {
                 self.framed.write(MasterResponse::ErrorNotReady),
             CommandError::UnknownService =>
                 self.framed.write(MasterResponse::ErrorUnknownService),
+            CommandError::UnknownWorker =>
+                self.framed.write(MasterResponse::ErrorUnknownWorker),
             CommandError::ServiceStopped =>
                 self.framed.write(MasterResponse::ErrorServiceStopped),
+            CommandError::Config(err) =>
+                self.framed.write(MasterResponse::ErrorConfig(err)),
             CommandError::Service(err) => match err {
                 ServiceOperationError::Starting =>
                     self.framed.write(MasterResponse::ErrorServiceStarting),
@@ -120,6 +128,12 @@ impl MasterClient {
                     self.framed.write(MasterResponse::ErrorServiceStopped),
                 ServiceOperationError::Failed =>
                     self.framed.write(MasterResponse::ErrorServiceFailed),
+                ServiceOperationError::Config(err) =>
+                    self.framed.write(MasterResponse::ErrorConfig(err)),
+                ServiceOperationError::NoRevision =>
+                    self.framed.write(MasterResponse::ErrorNoRevision),
+                ServiceOperationError::Scaling =>
+                    self.framed.write(MasterResponse::ErrorServiceScaling),
             }
         };
     }
@@ -144,11 +158,19 @@ impl MasterClient {
             }).spawn(ctx);
     }
 
-    fn reload(&mut self, name: String, ctx: &mut Context<Self>, graceful: bool)
+    fn reload(&mut self, name: String, ctx: &mut Context<Self>,
+              graceful: bool, canary: Option<Canary>)
     {
         info!("Client command: Reload service '{}'", name);
+        self.reload_status(cmd::ReloadService(name, graceful, canary), ctx);
+    }
 
-        self.cmd.send(cmd::ReloadService(name, graceful))
+    /// Send reload command and report reload status
+    fn reload_status<M>(&mut self, msg: M, ctx: &mut Context<Self>)
+        where M: Message<Result=Result<ReloadStatus, CommandError>> + 'static,
+              CommandCenter: Handler<M>
+    {
+        self.cmd.send(msg)
             .into_actor(self)
             .then(|res, srv, ctx| {
                 match res {
@@ -158,8 +180,10 @@ impl MasterClient {
                         match res {
                             ReloadStatus::Success =>
                                 srv.framed.write(MasterResponse::ServiceStarted),
-                            ReloadStatus::Failed =>
+                            ReloadStatus::Failed(None) =>
                                 srv.framed.write(MasterResponse::ServiceFailed),
+                            ReloadStatus::Failed(Some(reason)) =>
+                                srv.framed.write(MasterResponse::ServiceReloadFailed(reason)),
                             ReloadStatus::Stopping =>
                                 srv.framed.write(MasterResponse::ErrorServiceStopping),
                         };
@@ -169,6 +193,75 @@ impl MasterClient {
             }).spawn(ctx);
     }
 
+    fn reload_config(&mut self, apply: bool, ctx: &mut Context<Self>) {
+        info!("Client command: Reload configuration, apply: {}", apply);
+
+        self.cmd.send(cmd::ReloadConfig(apply))
+            .into_actor(self)
+            .then(|res, srv, ctx| {
+                match res {
+                    Err(_) => (),
+                    Ok(Err(err)) => srv.handle_error(err, ctx),
+                    Ok(Ok(changes)) =>
+                        srv.framed.write(MasterResponse::ConfigChanges(changes)),
+                }
+                actix::fut::ok(())
+            }).spawn(ctx);
+    }
+
+    /// Write log lines, split into several responses if necessary
+    fn write_lines(&mut self, lines: Vec<LogLine>) {
+        let mut chunk = Vec::new();
+        let mut size = 0;
+        for line in lines {
+            let line_size = json::to_vec(&line).map(|buf| buf.len()).unwrap_or(0);
+            if size + line_size > MAX_LOG_LINES_SIZE && !chunk.is_empty() {
+                self.framed.write(MasterResponse::LogLines(chunk));
+                chunk = Vec::new();
+                size = 0;
+            }
+            size += line_size;
+            chunk.push(line);
+        }
+        if !chunk.is_empty() {
+            self.framed.write(MasterResponse::LogLines(chunk));
+        }
+    }
+
+    fn logs(&mut self, req: LogsRequest, ctx: &mut Context<Self>) {
+        debug!("Client command: Service logs '{}'", req.name);
+
+        self.cmd.send(cmd::ServiceLogs(req.name, req.worker, req.lines))
+            .into_actor(self)
+            .then(|res, srv, ctx| {
+                match res {
+                    Err(_) => (),
+                    Ok(Err(err)) => srv.handle_error(err, ctx),
+                    Ok(Ok(lines)) => {
+                        srv.write_lines(lines);
+                        srv.framed.write(MasterResponse::Done);
+                    }
+                }
+                actix::fut::ok(())
+            }).spawn(ctx);
+    }
+
+    fn tail(&mut self, req: LogsRequest, ctx: &mut Context<Self>) {
+        debug!("Client command: Tail service '{}'", req.name);
+
+        let addr: Addr<Unsync, _> = ctx.address();
+        self.cmd.send(cmd::TailService(req.name, req.worker, req.lines, addr.recipient()))
+            .into_actor(self)
+            .then(|res, srv, ctx| {
+                match res {
+                    Err(_) => (),
+                    Ok(Err(err)) => srv.handle_error(err, ctx),
+                    Ok(Ok(_)) => (),
+                }
+                actix::fut::ok(())
+            }).spawn(ctx);
+    }
+
     fn start_service(&mut self, name: String, ctx: &mut Context<Self>) {
         info!("Client command: Start service '{}'", name);
 
@@ -198,6 +291,15 @@ impl Message for MasterRequest {
     type Result = ();
 }
 
+/// Output of tailed service
+impl Handler<LogLines> for MasterClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: LogLines, _: &mut Context<Self>) {
+        self.write_lines(msg.0);
+    }
+}
+
 impl Handler<MasterRequest> for MasterClient {
     type Result = ();
 
@@ -209,9 +311,22 @@ impl Handler<MasterRequest> for MasterClient {
             MasterRequest::Start(name) =>
                 self.start_service(name, ctx),
             MasterRequest::Reload(name) =>
-                self.reload(name, ctx, true),
+                self.reload(name, ctx, true, None),
+            MasterRequest::CanaryReload(req) => {
+                let canary = Canary { workers: req.workers, observe: req.observe };
+                self.reload(req.name, ctx, true, Some(canary))
+            }
             MasterRequest::Restart(name) =>
-                self.reload(name, ctx, false),
+                self.reload(name, ctx, false, None),
+            MasterRequest::Deploy(req) => {
+                info!("Client command: Deploy service '{}'", req.name);
+                let canary = req.canary.map(|workers| Canary { workers, observe: req.observe });
+                self.reload_status(cmd::DeployService(req.name, req.settings, canary), ctx)
+            }
+            MasterRequest::Rollback(name) => {
+                info!("Client command: Rollback service '{}'", name);
+                self.reload_status(cmd::RollbackService(name), ctx)
+            }
             MasterRequest::Stop(name) =>
                 self.stop(name, ctx),
             MasterRequest::Pause(name) => {
@@ -229,6 +344,21 @@ impl Handler<MasterRequest> for MasterClient {
                         actix::fut::ok(())
                     }).spawn(ctx);
             }
+            MasterRequest::Scale(name, num) => {
+                info!("Client command: Scale service '{}' to {} workers", name, num);
+                self.cmd.send(cmd::ScaleService(name, num))
+                    .into_actor(self)
+                    .then(|res, srv, ctx| {
+                        match res {
+                            Err(_) => (),
+                            Ok(Err(err)) => srv.handle_error(err, ctx),
+                            Ok(Ok(_)) => {
+                                srv.framed.write(MasterResponse::Done);
+                            },
+                        };
+                        actix::fut::ok(())
+                    }).spawn(ctx);
+            }
             MasterRequest::Resume(name) => {
                 info!("Client command: Resume service '{}'", name);
                 self.cmd.send(cmd::ResumeService(name))
@@ -282,6 +412,14 @@ impl Handler<MasterRequest> for MasterClient {
                 self.framed.write(MasterResponse::Version(
                     format!("{} {}", PKG_INFO.name, PKG_INFO.version)));
             },
+            MasterRequest::Reread =>
+                self.reload_config(false, ctx),
+            MasterRequest::Update =>
+                self.reload_config(true, ctx),
+            MasterRequest::Logs(req) =>
+                self.logs(req, ctx),
+            MasterRequest::Tail(req) =>
+                self.tail(req, ctx),
             MasterRequest::Quit => {
                 self.cmd.send(cmd::Stop)
                     .into_actor(self)
@@ -421,20 +559,17 @@ pub fn start(cfg: Config) -> bool {
         return false
     }
 
-    // set uid
-    if let Some(uid) = cfg.master.uid {
-        if let Err(err) = nix::unistd::setuid(uid) {
-            error!("Can not set process uid, err: {}", err);
-            return false
-        }
-    }
-
-    // set gid
-    if let Some(gid) = cfg.master.gid {
-        if let Err(err) = nix::unistd::setgid(gid) {
-            error!("Can not set process gid, err: {}", err);
-            return false
-        }
+    // set groups, gid and uid
+    let privileges = Privileges {
+        uid: cfg.master.uid,
+        gid: cfg.master.gid,
+        groups: cfg.master.groups.as_deref(),
+        capabilities: &[],
+        no_new_privs: false,
+    };
+    if let Err(err) = privileges.apply() {
+        error!("Master process {}", err);
+        return false
     }
 
     let daemon = cfg.master.daemon;
@@ -473,8 +608,6 @@ pub fn start(cfg: Config) -> bool {
         nix::sys::stat::umask(nix::sys::stat::Mode::from_bits(0o22).unwrap());
     }
 
-    let cfg = Rc::new(cfg);
-
     // create uds stream
     let lst = match UnixListener::from_listener(lst, Arbiter::handle()) {
         Ok(lst) => lst,
@@ -485,12 +618,13 @@ pub fn start(cfg: Config) -> bool {
     };
 
     // command center
-    let cmd = CommandCenter::start(cfg.clone());
+    let master_cfg = cfg.master.clone();
+    let cmd = CommandCenter::start(cfg);
 
     // start uds master server
     let _: () = Master::create(|ctx| {
         ctx.add_stream(lst.incoming().map(|(s, a)| NetStream(s, a)));
-        Master{cfg, cmd}}
+        Master{cfg: master_cfg, cmd}}
     );
 
     if !daemon {
@@ -1,4 +1,4 @@
-use event::ServiceStatus;
+use event::{LogLine, Reason, ServiceStatus};
 
 /// Master command
 #[allow(non_camel_case_types)]
@@ -19,16 +19,67 @@ pub enum MasterRequest {
     Resume(String),
     /// Gracefully reload service
     Reload(String),
+    /// Gracefully reload canary workers, observe them and reload the rest
+    CanaryReload(CanaryRequest),
     /// Restart service
     Restart(String),
+    /// Deploy new configuration revision
+    Deploy(DeployRequest),
+    /// Reload service with previous configuration revision
+    Rollback(String),
     /// Gracefully stop service
     Stop(String),
+    /// Change number of service workers
+    Scale(String, u16),
     /// Pid of the master process
     Pid,
     /// Quit process
     Quit,
     /// Version if the master
     Version,
+    /// Re-read configuration file and report changes
+    Reread,
+    /// Re-read configuration file and apply changes
+    Update,
+    /// Recent output of service workers
+    Logs(LogsRequest),
+    /// Recent output of service workers, followed by new output
+    Tail(LogsRequest),
+}
+
+/// Worker output request
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LogsRequest {
+    /// Service name
+    pub name: String,
+    /// Worker number, output of all workers if not set
+    pub worker: Option<usize>,
+    /// Number of recent lines
+    pub lines: usize,
+}
+
+/// Canary reload request
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CanaryRequest {
+    /// Service name
+    pub name: String,
+    /// Number of canary workers
+    pub workers: usize,
+    /// Observation period in seconds
+    pub observe: u64,
+}
+
+/// Deploy request
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeployRequest {
+    /// Service name
+    pub name: String,
+    /// `key=value` configuration settings
+    pub settings: Vec<(String, String)>,
+    /// Number of canary workers
+    pub canary: Option<usize>,
+    /// Canary observation period in seconds
+    pub observe: u64,
 }
 
 /// Master responses
@@ -49,15 +100,23 @@ pub enum MasterResponse {
     ServiceStopped,
     /// Service failed, service is not available
     ServiceFailed,
+    /// Service reload failed, old workers keep running
+    ServiceReloadFailed(Reason),
     /// Service status
     ServiceStatus(ServiceStatus),
     /// Service workers pids
     ServiceWorkerPids(Vec<String>),
+    /// Configuration changes
+    ConfigChanges(ConfigChanges),
+    /// Worker output
+    LogLines(Vec<LogLine>),
 
     /// System not ready
     ErrorNotReady,
     /// Service is unknown
     ErrorUnknownService,
+    /// Worker is unknown
+    ErrorUnknownWorker,
     /// Service is starting
     ErrorServiceStarting,
     /// Service is running
@@ -66,8 +125,38 @@ pub enum MasterResponse {
     ErrorServiceReloading,
     /// Service is stopping
     ErrorServiceStopping,
+    /// Workers of previous scale down are stopping
+    ErrorServiceScaling,
     /// Service is stopped
     ErrorServiceStopped,
     /// Service is failed
     ErrorServiceFailed,
+    /// Configuration file error
+    ErrorConfig(String),
+    /// Service has no previous configuration revision
+    ErrorNoRevision,
+}
+
+/// Difference between running and new configuration
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ConfigChanges {
+    /// Changes are applied
+    pub applied: bool,
+    pub services_added: Vec<String>,
+    pub services_removed: Vec<String>,
+    /// Services that get reloaded, configuration or sockets changed
+    pub services_changed: Vec<String>,
+    pub sockets_added: Vec<String>,
+    pub sockets_removed: Vec<String>,
+    pub sockets_changed: Vec<String>,
+    /// Errors during applying changes
+    pub errors: Vec<String>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.services_added.is_empty() && self.services_removed.is_empty() &&
+            self.services_changed.is_empty() && self.sockets_added.is_empty() &&
+            self.sockets_removed.is_empty() && self.sockets_changed.is_empty()
+    }
 }
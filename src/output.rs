@@ -0,0 +1,294 @@
+// Worker stdout/stderr capture
+//
+// Master reads worker output from pipes, log files are written by
+// separate thread so slow disk never blocks master event loop.
+use std;
+use std::io;
+use std::rc::Rc;
+use std::thread;
+use std::io::Write;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+
+use time;
+use bytes::BytesMut;
+use tokio_io::codec::Decoder;
+use nix::unistd::Pid;
+
+use config::ServiceConfig;
+use event::{LogLine, OutputStream};
+
+/// Number of lines buffered for log writer
+const QUEUE_SIZE: usize = 4096;
+
+/// Long lines are split into chunks of this size,
+/// line has to fit into control protocol frame
+const MAX_LINE_SIZE: usize = 8192;
+
+
+/// Service log, writes worker output to log files or to master stdout/stderr
+pub struct ServiceLog {
+    stdout: Rc<LogWriter>,
+    stderr: Rc<LogWriter>,
+}
+
+impl ServiceLog {
+
+    pub fn new(cfg: &ServiceConfig) -> ServiceLog {
+        let rotation = Rotation {
+            max_size: cfg.log_max_size,
+            max_age: cfg.log_max_age.map(|secs| Duration::new(secs, 0)),
+            backups: cfg.log_backups,
+        };
+        let directory = Path::new(cfg.directory.as_deref().unwrap_or(""));
+        let stdout_path = cfg.stdout.as_ref().map(|p| directory.join(p));
+        let stderr_path = cfg.stderr.as_ref().map(|p| directory.join(p));
+
+        let stdout = Rc::new(LogWriter::start(
+            &cfg.name, stdout_path.clone(), OutputStream::Stdout, rotation.clone()));
+        let stderr = if stderr_path.is_some() && stderr_path == stdout_path {
+            Rc::clone(&stdout)
+        } else {
+            Rc::new(LogWriter::start(&cfg.name, stderr_path, OutputStream::Stderr, rotation))
+        };
+        ServiceLog { stdout, stderr }
+    }
+
+    /// Write line of worker output
+    pub fn write(&self, name: &str, idx: usize, pid: Pid, stream: OutputStream, line: &str) {
+        let t = time::now();
+        let line = format!("{},{:03} {} worker({}) (pid:{}) {}\n",
+                           time::strftime("%Y-%m-%d %H:%M:%S", &t).unwrap(),
+                           t.tm_nsec / 1_000_000, name, idx + 1, pid, line);
+        match stream {
+            OutputStream::Stdout => self.stdout.send(LogCommand::Line(line)),
+            OutputStream::Stderr => self.stderr.send(LogCommand::Line(line)),
+        }
+    }
+
+    /// Reopen log files, i.e. after external rotation
+    pub fn reopen(&self) {
+        self.stdout.send(LogCommand::Reopen);
+        if !Rc::ptr_eq(&self.stdout, &self.stderr) {
+            self.stderr.send(LogCommand::Reopen);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Rotation {
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    backups: u16,
+}
+
+enum LogCommand {
+    Line(String),
+    Reopen,
+}
+
+struct LogWriter {
+    name: String,
+    tx: SyncSender<LogCommand>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl LogWriter {
+
+    fn start(name: &str, path: Option<PathBuf>, stream: OutputStream, rotation: Rotation)
+             -> LogWriter
+    {
+        let (tx, rx) = sync_channel(QUEUE_SIZE);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&dropped);
+        let srv = name.to_owned();
+
+        let res = thread::Builder::new()
+            .name(format!("log-{}", name))
+            .spawn(move || {
+                let mut file = LogFile::new(path, stream, rotation);
+                for cmd in rx {
+                    match cmd {
+                        LogCommand::Line(line) => {
+                            let dropped = counter.swap(0, Ordering::Relaxed);
+                            if dropped != 0 {
+                                file.write(&format!(
+                                    "{} lines of `{}` service output dropped\n", dropped, srv));
+                            }
+                            file.write(&line);
+                        }
+                        LogCommand::Reopen => file.reopen(),
+                    }
+                }
+            });
+        if let Err(err) = res {
+            error!("Can not start log writer for {:?}: {}", name, err);
+        }
+
+        LogWriter { name: name.to_owned(), tx, dropped }
+    }
+
+    /// Queue command, lines are dropped if writer falls behind
+    fn send(&self, cmd: LogCommand) {
+        match self.tx.try_send(cmd) {
+            Ok(_) => (),
+            Err(TrySendError::Full(LogCommand::Line(_))) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("Log writer of {:?} service falls behind, dropping lines", self.name);
+                }
+            }
+            Err(TrySendError::Full(LogCommand::Reopen)) =>
+                warn!("Log writer of {:?} service is busy, can not reopen log", self.name),
+            Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+}
+
+/// Log file with rotation, writes to master stdout/stderr if path is not set
+struct LogFile {
+    path: Option<PathBuf>,
+    stream: OutputStream,
+    rotation: Rotation,
+    file: Option<std::fs::File>,
+    size: u64,
+    opened: Instant,
+}
+
+impl LogFile {
+
+    fn new(path: Option<PathBuf>, stream: OutputStream, rotation: Rotation) -> LogFile {
+        let mut file = LogFile {
+            path, stream, rotation, file: None, size: 0, opened: Instant::now() };
+        file.reopen();
+        file
+    }
+
+    fn reopen(&mut self) {
+        self.file = None;
+        if let Some(ref path) = self.path {
+            match std::fs::OpenOptions::new().append(true).create(true).open(path) {
+                Ok(f) => {
+                    self.size = f.metadata().map(|m| m.len()).unwrap_or(0);
+                    self.opened = Instant::now();
+                    self.file = Some(f);
+                }
+                Err(err) => error!("Can not open log file {:?}: {}", path, err),
+            }
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.path.is_none() {
+            let _ = match self.stream {
+                OutputStream::Stdout => io::stdout().write_all(line.as_bytes()),
+                OutputStream::Stderr => io::stderr().write_all(line.as_bytes()),
+            };
+            return
+        }
+
+        if self.need_rotate() {
+            self.rotate();
+        }
+        if let Some(ref mut file) = self.file {
+            if file.write_all(line.as_bytes()).is_ok() {
+                self.size += line.len() as u64;
+            }
+        }
+    }
+
+    fn need_rotate(&self) -> bool {
+        if let Some(max_size) = self.rotation.max_size {
+            if self.size >= max_size {
+                return true
+            }
+        }
+        if let Some(max_age) = self.rotation.max_age {
+            if self.size > 0 && self.opened.elapsed() >= max_age {
+                return true
+            }
+        }
+        false
+    }
+
+    /// Rename `path` to `path.1`, `path.1` to `path.2`, etc
+    fn rotate(&mut self) {
+        if let Some(path) = self.path.clone() {
+            self.file = None;
+            let backup = |n: u16| PathBuf::from(format!("{}.{}", path.display(), n));
+
+            if self.rotation.backups == 0 {
+                let _ = std::fs::remove_file(&path);
+            } else {
+                let _ = std::fs::remove_file(backup(self.rotation.backups));
+                for n in (1..self.rotation.backups).rev() {
+                    let _ = std::fs::rename(backup(n), backup(n + 1));
+                }
+                if let Err(err) = std::fs::rename(&path, backup(1)) {
+                    error!("Can not rotate log file {:?}: {}", path, err);
+                }
+            }
+            self.reopen();
+        }
+    }
+}
+
+/// Recent output of worker
+pub struct LogBuffer {
+    max: usize,
+    lines: VecDeque<(u64, LogLine)>,
+}
+
+impl LogBuffer {
+    pub fn new(max: usize) -> LogBuffer {
+        LogBuffer { max, lines: VecDeque::new() }
+    }
+
+    /// Add line, `seq` is service wide line number
+    pub fn add(&mut self, seq: u64, line: LogLine) {
+        if self.lines.len() >= self.max {
+            self.lines.pop_front();
+        }
+        self.lines.push_back((seq, line));
+    }
+
+    pub fn lines(&self) -> &VecDeque<(u64, LogLine)> {
+        &self.lines
+    }
+}
+
+/// Splits worker output into lines
+pub struct OutputCodec;
+
+impl Decoder for OutputCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(pos) = src.iter().position(|b| *b == b'\n') {
+            let buf = src.split_to(pos + 1);
+            let line = String::from_utf8_lossy(&buf[..pos]);
+            Ok(Some(line.trim_end_matches('\r').to_owned()))
+        } else if src.len() >= MAX_LINE_SIZE {
+            let buf = src.split_to(MAX_LINE_SIZE);
+            Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(line) => Ok(Some(line)),
+            None => if src.is_empty() {
+                Ok(None)
+            } else {
+                let buf = src.take();
+                Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+            }
+        }
+    }
+}
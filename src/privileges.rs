@@ -0,0 +1,176 @@
+// Process privileges management, shared by master and worker processes
+use std::ptr;
+use std::ffi::{CStr, CString};
+
+use libc;
+use nix::unistd::{self, Gid, Uid};
+
+
+/// Linux capabilities that can be retained after uid change
+const CAPABILITIES: &[(&str, u32)] = &[
+    ("CAP_CHOWN", 0),
+    ("CAP_DAC_OVERRIDE", 1),
+    ("CAP_DAC_READ_SEARCH", 2),
+    ("CAP_FOWNER", 3),
+    ("CAP_KILL", 5),
+    ("CAP_SETGID", 6),
+    ("CAP_SETUID", 7),
+    ("CAP_NET_BIND_SERVICE", 10),
+    ("CAP_NET_BROADCAST", 11),
+    ("CAP_NET_ADMIN", 12),
+    ("CAP_NET_RAW", 13),
+    ("CAP_IPC_LOCK", 14),
+    ("CAP_SYS_CHROOT", 18),
+    ("CAP_SYS_PTRACE", 19),
+    ("CAP_SYS_ADMIN", 21),
+    ("CAP_SYS_NICE", 23),
+    ("CAP_SYS_RESOURCE", 24),
+];
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Convert capability name to capability number
+pub fn capability(name: &str) -> Option<u32> {
+    let name = name.trim().to_uppercase();
+    let name = if name.starts_with("CAP_") { name } else { format!("CAP_{}", name) };
+    CAPABILITIES.iter().find(|cap| cap.0 == name).map(|cap| cap.1)
+}
+
+/// Process credentials
+pub struct Privileges<'a> {
+    pub uid: Option<Uid>,
+    pub gid: Option<Gid>,
+    /// Supplementary groups, by default groups of `uid` user from group database
+    pub groups: Option<&'a [Gid]>,
+    /// Capabilities retained after uid change
+    pub capabilities: &'a [String],
+    /// Set `no_new_privs` process flag
+    pub no_new_privs: bool,
+}
+
+impl<'a> Privileges<'a> {
+
+    /// Change process credentials.
+    ///
+    /// Supplementary groups are set first, then gid and uid.
+    pub fn apply(&self) -> Result<(), String> {
+        let user = self.uid.and_then(user_entry);
+
+        // user's primary group, if gid is not set explicitly
+        let gid = self.gid.or_else(|| user.as_ref().map(|u| u.1));
+
+        // supplementary groups
+        if let Some(groups) = self.groups {
+            let groups: Vec<libc::gid_t> = groups.iter().map(|g| libc::gid_t::from(*g)).collect();
+            if unsafe { libc::setgroups(groups.len(), groups.as_ptr()) } != 0 {
+                return Err(format!("Can not set supplementary groups, err: {}",
+                                   ::std::io::Error::last_os_error()))
+            }
+        } else if self.uid.is_some() && unistd::geteuid().is_root() {
+            let res = match (user.as_ref(), gid) {
+                (Some(user), Some(gid)) => unsafe {
+                    libc::initgroups(user.0.as_ptr(), gid.into())
+                },
+                // user is not in passwd database, drop all groups
+                _ => unsafe { libc::setgroups(0, ptr::null()) },
+            };
+            if res != 0 {
+                return Err(format!("Can not initialize supplementary groups, err: {}",
+                                   ::std::io::Error::last_os_error()))
+            }
+        }
+
+        if let Some(gid) = gid {
+            if let Err(err) = unistd::setgid(gid) {
+                return Err(format!("Can not set gid, err: {}", err))
+            }
+        }
+
+        if let Some(uid) = self.uid {
+            let caps = self.capabilities_mask()?;
+            if caps != 0 {
+                prctl(libc::PR_SET_KEEPCAPS, 1, 0)
+                    .map_err(|err| format!("Can not keep capabilities, err: {}", err))?;
+            }
+            if let Err(err) = unistd::setuid(uid) {
+                return Err(format!("Can not set uid, err: {}", err))
+            }
+            if caps != 0 {
+                set_capabilities(caps)?;
+            }
+        }
+
+        if self.no_new_privs {
+            prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0)
+                .map_err(|err| format!("Can not set no_new_privs flag, err: {}", err))?;
+        }
+        Ok(())
+    }
+
+    fn capabilities_mask(&self) -> Result<u32, String> {
+        let mut mask = 0;
+        for name in self.capabilities {
+            match capability(name) {
+                Some(cap) => mask |= 1 << cap,
+                None => return Err(format!("Unknown capability: {}", name)),
+            }
+        }
+        Ok(mask)
+    }
+}
+
+/// Limit process capabilities to `mask` and make them ambient,
+/// so they are preserved across execve
+fn set_capabilities(mask: u32) -> Result<(), String> {
+    let mut header = CapUserHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    let mut data = [CapUserData::default(); 2];
+    data[0] = CapUserData { effective: mask, permitted: mask, inheritable: mask };
+
+    if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_mut_ptr()) } != 0 {
+        return Err(format!("Can not set capabilities, err: {}",
+                           ::std::io::Error::last_os_error()))
+    }
+    for cap in CAPABILITIES.iter().filter(|cap| mask & (1 << cap.1) != 0) {
+        prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong, cap.1.into())
+            .map_err(|err| format!("Can not raise {} capability, err: {}", cap.0, err))?;
+    }
+    let _ = prctl(libc::PR_SET_KEEPCAPS, 0, 0);
+    Ok(())
+}
+
+fn prctl(option: libc::c_int, arg2: libc::c_ulong, arg3: libc::c_ulong)
+         -> Result<(), ::std::io::Error>
+{
+    if unsafe { libc::prctl(option, arg2, arg3, 0, 0) } != 0 {
+        Err(::std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// User name and primary group from passwd database
+fn user_entry(uid: Uid) -> Option<(CString, Gid)> {
+    unsafe {
+        let pw = libc::getpwuid(uid.into());
+        if pw.is_null() {
+            None
+        } else {
+            let name = CStr::from_ptr((*pw).pw_name).to_owned();
+            Some((name, Gid::from_raw((*pw).pw_gid)))
+        }
+    }
+}
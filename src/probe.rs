@@ -0,0 +1,173 @@
+// Worker readiness and liveness probes
+//
+// Probes run on master event loop, exec probe command is reaped by master's
+// SIGCHLD handler, so exit code is reported through stdout of wrapper shell.
+use std;
+use std::io;
+use std::net::{self, SocketAddr};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use actix::Arbiter;
+use futures::Async;
+use futures::future::{self, Either, Future};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{PollEvented, Timeout};
+use tokio_io;
+use nix;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::socket::{self, sockopt, AddressFamily, InetAddr, SockAddr, SockType,
+                       SOCK_CLOEXEC, SOCK_NONBLOCK};
+use nix::unistd::Pid;
+
+use config::{ProbeConfig, ProbeKind};
+use io::Io;
+
+pub type ProbeFuture = Box<dyn Future<Item=(), Error=String>>;
+
+/// Run probe, resolves with error description if worker is not healthy
+pub fn check(probe: &ProbeConfig, directory: Option<&str>, idx: usize, pid: Pid) -> ProbeFuture {
+    let fut = match probe.kind {
+        ProbeKind::Tcp => Box::new(connect(probe).map(|_| ())),
+        ProbeKind::Http => http(probe),
+        ProbeKind::Exec => exec(probe, directory, idx, pid),
+        ProbeKind::File => {
+            let path = substitute(probe.path.as_deref().unwrap_or(""), idx, pid);
+            let path = std::path::Path::new(directory.unwrap_or("")).join(path);
+            Box::new(future::result(
+                if path.exists() { Ok(()) } else { Err(format!("{:?} does not exist", path)) }))
+        }
+    };
+
+    let timeout = match Timeout::new(Duration::new(u64::from(probe.timeout), 0), Arbiter::handle()) {
+        Ok(timeout) => timeout,
+        Err(err) => return Box::new(future::err(format!("{}", err))),
+    };
+    Box::new(fut.select2(timeout).then(|res| match res {
+        Ok(Either::A(_)) => Ok(()),
+        Ok(Either::B(_)) => Err("Probe timeout".to_owned()),
+        Err(Either::A((err, _))) => Err(err),
+        Err(Either::B((err, _))) => Err(format!("{}", err)),
+    }))
+}
+
+/// Replace `{worker}` and `{pid}` placeholders
+fn substitute(s: &str, idx: usize, pid: Pid) -> String {
+    s.replace("{worker}", &(idx + 1).to_string()).replace("{pid}", &pid.to_string())
+}
+
+fn connect(probe: &ProbeConfig) -> Box<dyn Future<Item=TcpStream, Error=String>> {
+    let addr = match probe.addr {
+        Some(addr) => addr,
+        None => return Box::new(future::err(format!("Address of {} is not resolved", probe.host))),
+    };
+
+    let mut stream = match start_connect(&addr) {
+        Ok(stream) => Some(stream),
+        Err(err) => return Box::new(future::err(format!("Can not connect to {}: {}", addr, err))),
+    };
+    Box::new(
+        future::poll_fn(move || {
+            if let Some(Async::NotReady) = stream.as_ref().map(|s| s.poll_write()) {
+                return Ok(Async::NotReady)
+            }
+            let stream = stream.take().expect("polled after completion");
+            match socket::getsockopt(stream.as_raw_fd(), sockopt::SocketError) {
+                Ok(0) => Ok(Async::Ready(stream)),
+                Ok(errno) => Err(io::Error::from_raw_os_error(errno)),
+                Err(err) => Err(io::Error::other(err)),
+            }
+        }).map_err(move |err| format!("Can not connect to {}: {}", addr, err)))
+}
+
+/// Start non-blocking connect, mio can not convert socket addresses of current std
+fn start_connect(addr: &SocketAddr) -> io::Result<TcpStream> {
+    let family = if addr.is_ipv4() { AddressFamily::Inet } else { AddressFamily::Inet6 };
+    let fd = socket::socket(family, SockType::Stream, SOCK_NONBLOCK | SOCK_CLOEXEC, 0)
+        .map_err(io::Error::other)?;
+    let stream = unsafe { net::TcpStream::from_raw_fd(fd) };
+    match socket::connect(fd, &SockAddr::new_inet(InetAddr::from_std(addr))) {
+        Ok(_) | Err(nix::Error::Sys(Errno::EINPROGRESS)) => (),
+        Err(err) => return Err(io::Error::other(err)),
+    }
+    TcpStream::from_stream(stream, Arbiter::handle())
+}
+
+fn http(probe: &ProbeConfig) -> ProbeFuture {
+    let req = format!("GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: fectl\r\n\r\n",
+                      probe.path.as_deref().unwrap_or("/"), probe.host);
+    Box::new(
+        connect(probe)
+            .and_then(|stream| tokio_io::io::write_all(stream, req)
+                      .map_err(|err| format!("Can not send request: {}", err)))
+            .and_then(|(stream, _)| tokio_io::io::read(stream, vec![0; 1024])
+                      .map_err(|err| format!("Can not read response: {}", err)))
+            .and_then(|(_, buf, size)| {
+                // status line, i.e. "HTTP/1.1 200 OK"
+                let head = String::from_utf8_lossy(&buf[..size]);
+                let mut parts = head.split_whitespace();
+                let status = match (parts.next(), parts.next()) {
+                    (Some(proto), Some(status)) if proto.starts_with("HTTP/") =>
+                        status.parse::<u16>().ok(),
+                    _ => None,
+                };
+                match status {
+                    Some(status) if (200..300).contains(&status) => Ok(()),
+                    Some(status) => Err(format!("HTTP status {}", status)),
+                    None => Err("Can not parse HTTP response".to_owned()),
+                }
+            }))
+}
+
+/// Kills probe command process group if probe gets dropped
+struct ProbeProcess(i32);
+
+impl Drop for ProbeProcess {
+    fn drop(&mut self) {
+        let _ = kill(Pid::from_raw(-self.0), Signal::SIGKILL);
+    }
+}
+
+fn exec(probe: &ProbeConfig, directory: Option<&str>, idx: usize, pid: Pid) -> ProbeFuture {
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c").arg("\"$@\" >/dev/null 2>&1; echo $?").arg("fectl-probe")
+        .args(probe.command.iter().map(|arg| substitute(arg, idx, pid)))
+        .env("FECTL_PROC_IDX", idx.to_string())
+        .env("FECTL_PID", pid.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .process_group(0);
+    if let Some(dir) = directory {
+        cmd.current_dir(dir);
+    }
+
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(err) => return Box::new(future::err(format!("Can not run probe command: {}", err))),
+    };
+    let guard = ProbeProcess(child.id() as i32);
+    let stdout = match child.stdout.take() {
+        Some(stdout) => unsafe { Io::from_raw_fd(stdout.into_raw_fd()) },
+        None => return Box::new(future::err("Can not read probe command output".to_owned())),
+    };
+    let stdout = match PollEvented::new(stdout, Arbiter::handle()) {
+        Ok(stdout) => stdout,
+        Err(err) => return Box::new(future::err(format!("{}", err))),
+    };
+
+    Box::new(
+        tokio_io::io::read_to_end(stdout, Vec::new())
+            .map_err(|err| format!("Can not read probe command output: {}", err))
+            .and_then(move |(_, buf)| {
+                drop(guard);
+                match String::from_utf8_lossy(&buf).trim().parse::<i32>() {
+                    Ok(0) => Ok(()),
+                    Ok(code) => Err(format!("Probe command exit code: {}", code)),
+                    Err(_) => Err("Probe command failed".to_owned()),
+                }
+            }))
+}
@@ -3,7 +3,7 @@
 use std;
 use std::io;
 use std::error::Error;
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{FromRawFd, RawFd};
 use std::time::{Duration, Instant};
 
 use serde_json as json;
@@ -12,15 +12,21 @@ use bytes::{BytesMut, BufMut};
 use tokio_io::AsyncRead;
 use tokio_io::io::WriteHalf;
 use tokio_io::codec::{FramedRead, Encoder, Decoder};
+use tokio_core::reactor::PollEvented;
+use futures::{Future, Stream};
+use nix::fcntl::O_CLOEXEC;
 use nix::sys::signal::{kill, Signal};
-use nix::unistd::{close, pipe, fork, ForkResult, Pid};
+use nix::unistd::{close, pipe, pipe2, fork, ForkResult, Pid};
 
 use actix::prelude::*;
 
-use config::ServiceConfig;
-use io::PipeFile;
+use config::{ProbeConfig, Protocol, ServiceConfig, StopSignal};
+use io::{Io, PipeFile};
+use cgroup;
+use probe;
+use output::OutputCodec;
 use worker::{WorkerMessage, WorkerCommand};
-use event::Reason;
+use event::{OutputStream, Reason};
 use exec::exec_worker;
 use service::{self, FeService};
 
@@ -29,6 +35,9 @@ const WORKER_TIMEOUT: i32 = 98;
 pub const WORKER_INIT_FAILED: i32 = 99;
 pub const WORKER_BOOT_FAILED: i32 = 100;
 
+/// Stdout and stderr pipe descriptors
+type OutputFds = (RawFd, RawFd);
+
 pub struct Process {
     idx: usize,
     pid: Pid,
@@ -38,6 +47,13 @@ pub struct Process {
     timeout: Duration,
     startup_timeout: u64,
     shutdown_timeout: u64,
+    protocol: Protocol,
+    stop_signals: Vec<StopSignal>,
+    directory: Option<String>,
+    readiness: Option<ProbeConfig>,
+    liveness: Option<ProbeConfig>,
+    /// Number of consecutive liveness probe failures
+    probe_failures: u32,
     framed: actix::io::FramedWrite<WriteHalf<PipeFile>, TransportCodec>,
 }
 
@@ -53,8 +69,16 @@ impl Actor for Process {
 impl StreamHandler<ProcessMessage, io::Error> for Process {
 
     fn finished(&mut self, ctx: &mut Context<Self>) {
-        self.kill(ctx, false);
-        ctx.stop();
+        match self.protocol {
+            Protocol::Fectl => {
+                self.kill(ctx, false);
+                ctx.stop();
+            }
+            // pipe gets closed on successful exec
+            Protocol::None => if let ProcessState::Starting = self.state {
+                self.loaded(ctx);
+            }
+        }
     }
 
     fn handle(&mut self, msg: ProcessMessage, ctx: &mut Self::Context) {
@@ -65,6 +89,8 @@ impl StreamHandler<ProcessMessage, io::Error> for Process {
 #[derive(Debug)]
 enum ProcessState {
     Starting,
+    /// Process is loaded, waiting for readiness probe
+    Probing,
     Failed,
     Running,
     Stopping,
@@ -75,7 +101,10 @@ pub enum ProcessMessage {
     Message(WorkerMessage),
     StartupTimeout,
     StopTimeout,
+    StopSignal(usize),
     Heartbeat,
+    Readiness,
+    Liveness,
     Kill,
 }
 
@@ -99,6 +128,10 @@ pub enum ProcessError {
     Signal(usize),
     /// Worker exited with code
     ExitCode(i8),
+    /// Worker killed by OOM killer
+    OutOfMemory,
+    /// Liveness probe failed
+    ProbeFailed(String),
 }
 
 impl ProcessError {
@@ -127,6 +160,8 @@ impl<'a> std::convert::From<&'a ProcessError> for Reason
             ProcessError::BootFailed => Reason::BootFailed,
             ProcessError::Signal(sig) => Reason::Signal(sig),
             ProcessError::ExitCode(code) => Reason::ExitCode(code),
+            ProcessError::OutOfMemory => Reason::OutOfMemory,
+            ProcessError::ProbeFailed(ref err) => Reason::ProbeFailed(err.clone()),
         }
     }
 }
@@ -138,7 +173,7 @@ impl Process {
                  -> (Pid, Option<Addr<Unsync, Process>>)
     {
         // fork process and esteblish communication
-        let (pid, pipe) = match Process::fork(idx, cfg) {
+        let (pid, pipe) = match Process::fork(idx, cfg, &addr) {
             Ok(res) => res,
             Err(err) => {
                 let pid = Pid::from_raw(-1);
@@ -154,6 +189,11 @@ impl Process {
         let timeout = Duration::new(u64::from(cfg.timeout), 0);
         let startup_timeout = u64::from(cfg.startup_timeout);
         let shutdown_timeout = u64::from(cfg.shutdown_timeout);
+        let protocol = cfg.protocol;
+        let stop_signals = cfg.stop_signals.clone();
+        let directory = cfg.directory.clone();
+        let readiness = cfg.readiness.clone();
+        let liveness = cfg.liveness.clone();
 
         // start Process service
         let addr = Process::create(move |ctx| {
@@ -163,6 +203,8 @@ impl Process {
                              Duration::new(startup_timeout as u64, 0));
             Process {
                 idx, pid, addr, timeout, startup_timeout, shutdown_timeout,
+                protocol, stop_signals, directory, readiness, liveness,
+                probe_failures: 0,
                 state: ProcessState::Starting,
                 hb: Instant::now(),
                 framed: actix::io::FramedWrite::new(w, TransportCodec, ctx)
@@ -170,9 +212,19 @@ impl Process {
         (pid, Some(addr))
     }
 
-    fn fork(idx: usize, cfg: &ServiceConfig) -> Result<(Pid, PipeFile), io::Error>
+    fn fork(idx: usize, cfg: &ServiceConfig, addr: &Addr<Unsync, FeService>)
+            -> Result<(Pid, PipeFile), io::Error>
     {
         let (p_read, p_write, ch_read, ch_write) = Process::create_pipes()?;
+        let (out_read, out_write) = match Process::create_output_pipes() {
+            Ok(res) => res,
+            Err(err) => {
+                for fd in &[p_read, p_write, ch_read, ch_write] {
+                    let _ = close(*fd);
+                }
+                return Err(err)
+            }
+        };
 
         // fork
         let pid = match fork() {
@@ -180,15 +232,31 @@ impl Process {
             Ok(ForkResult::Child) => {
                 let _ = close(p_write);
                 let _ = close(ch_read);
-                exec_worker(idx, cfg, p_read, ch_write);
+                exec_worker(idx, cfg, p_read, ch_write, out_write);
                 unreachable!();
             },
             Err(err) => {
                 error!("Fork failed: {}", err.description());
+                for fd in &[p_read, p_write, ch_read, ch_write,
+                            out_read.0, out_read.1, out_write.0, out_write.1] {
+                    let _ = close(*fd);
+                }
                 return Err(io::Error::new(io::ErrorKind::Other, err.description()))
             }
         };
 
+        // move worker to its cgroup before it starts executing command
+        if let Err(err) = cgroup::attach(cfg, pid) {
+            error!("{}", err);
+        }
+
+        // read worker output until worker closes its side of pipes,
+        // output is processed even after process actor stops
+        let _ = close(out_write.0);
+        let _ = close(out_write.1);
+        Process::read_output(idx, pid, out_read.0, OutputStream::Stdout, addr.clone());
+        Process::read_output(idx, pid, out_read.1, OutputStream::Stderr, addr.clone());
+
         // initialize worker communication channel
         let _ = close(p_read);
         let _ = close(ch_write);
@@ -197,6 +265,41 @@ impl Process {
         Ok((pid, pipe))
     }
 
+    /// Pipes for worker stdout and stderr, returns read and write ends
+    fn create_output_pipes() -> Result<(OutputFds, OutputFds), io::Error> {
+        let (out_read, out_write) = pipe2(O_CLOEXEC).map_err(|err| io::Error::new(
+            io::ErrorKind::Other, format!("Can not create pipe: {}", err)))?;
+        let (err_read, err_write) = match pipe2(O_CLOEXEC) {
+            Ok(res) => res,
+            Err(err) => {
+                let _ = close(out_read);
+                let _ = close(out_write);
+                return Err(io::Error::new(
+                    io::ErrorKind::Other, format!("Can not create pipe: {}", err)))
+            }
+        };
+        Ok(((out_read, err_read), (out_write, err_write)))
+    }
+
+    fn read_output(idx: usize, pid: Pid, fd: RawFd,
+                   stream: OutputStream, addr: Addr<Unsync, FeService>)
+    {
+        let io = match PollEvented::new(unsafe{ Io::from_raw_fd(fd) }, Arbiter::handle()) {
+            Ok(io) => io,
+            Err(err) => {
+                error!("Can not read worker output: {}", err);
+                return
+            }
+        };
+        Arbiter::handle().spawn(
+            FramedRead::new(io, OutputCodec)
+                .for_each(move |line| {
+                    addr.do_send(service::ProcessOutput(idx, pid, stream, line));
+                    Ok(())
+                })
+                .map_err(move |err| warn!("Can not read output of worker {}: {}", pid, err)));
+    }
+
     fn create_pipes() -> Result<(RawFd, RawFd, RawFd, RawFd), io::Error> {
         // open communication pipes
         let (p_read, p_write) = match pipe() {
@@ -218,6 +321,55 @@ impl Process {
         Ok((p_read, p_write, ch_read, ch_write))
     }
 
+    /// Send command to worker, plain processes do not get commands
+    fn send(&mut self, cmd: WorkerCommand) {
+        if self.protocol == Protocol::Fectl {
+            self.framed.write(cmd);
+        }
+    }
+
+    fn loaded(&mut self, ctx: &mut Context<Self>) {
+        debug!("Worker loaded (pid:{})", self.pid);
+        if self.readiness.is_some() {
+            self.state = ProcessState::Probing;
+            ctx.notify(ProcessMessage::Readiness);
+        } else {
+            self.running(ctx);
+        }
+    }
+
+    fn running(&mut self, ctx: &mut Context<Self>) {
+        self.addr.do_send(service::ProcessLoaded(self.idx, self.pid));
+        self.state = ProcessState::Running;
+
+        // start heartbeat timer
+        if self.protocol == Protocol::Fectl {
+            self.hb = Instant::now();
+            ctx.notify_later(ProcessMessage::Heartbeat, Duration::new(HEARTBEAT, 0));
+        }
+        if let Some(ref probe) = self.liveness {
+            ctx.notify_later(
+                ProcessMessage::Liveness, Duration::new(u64::from(probe.interval), 0));
+        }
+    }
+
+    fn probe(&self, probe: &ProbeConfig) -> probe::ProbeFuture {
+        probe::check(probe, self.directory.as_deref(), self.idx, self.pid)
+    }
+
+    /// Send `step` signal of stop sequence and wait for worker exit
+    fn stop_step(&mut self, step: usize, ctx: &mut Context<Self>) {
+        match self.stop_signals.get(step) {
+            Some(stop) => {
+                let _ = kill(self.pid, stop.signal);
+                ctx.notify_later(
+                    ProcessMessage::StopSignal(step + 1),
+                    Duration::new(stop.timeout.unwrap_or(self.shutdown_timeout), 0));
+            }
+            None => ctx.notify(ProcessMessage::StopTimeout),
+        }
+    }
+
     fn kill(&self, ctx: &mut Context<Self>, graceful: bool) {
         if graceful {
             ctx.notify_later(ProcessMessage::Kill, Duration::new(1, 0));
@@ -244,28 +396,23 @@ impl Handler<ProcessMessage> for Process {
             ProcessMessage::Message(msg) => match msg {
                 WorkerMessage::forked => {
                     debug!("Worker forked (pid:{})", self.pid);
-                    self.framed.write(WorkerCommand::prepare);
+                    self.send(WorkerCommand::prepare);
                 }
                 WorkerMessage::loaded => {
                     match self.state {
-                        ProcessState::Starting => {
-                            debug!("Worker loaded (pid:{})", self.pid);
-                            self.addr.do_send(
-                                service::ProcessLoaded(self.idx, self.pid));
-
-                            // start heartbeat timer
-                            self.state = ProcessState::Running;
-                            self.hb = Instant::now();
-                            ctx.notify_later(
-                                ProcessMessage::Heartbeat, Duration::new(HEARTBEAT, 0));
-                        },
+                        ProcessState::Starting => self.loaded(ctx),
                         _ => {
                             warn!("Received `loaded` message from worker (pid:{})", self.pid);
                         }
                     }
                 }
-                WorkerMessage::hb => {
+                WorkerMessage::hb(load) => {
                     self.hb = Instant::now();
+                    if load.is_some() {
+                        self.addr.do_send(
+                            service::ProcessMessage(
+                                self.idx, self.pid, WorkerMessage::hb(load)));
+                    }
                 }
                 WorkerMessage::reload => {
                     // worker requests reload
@@ -289,7 +436,7 @@ impl Handler<ProcessMessage> for Process {
                 }
             }
             ProcessMessage::StartupTimeout => {
-                if let ProcessState::Starting = self.state {
+                if let ProcessState::Starting | ProcessState::Probing = self.state {
                     error!("Worker startup timeout after {} secs", self.startup_timeout);
                     self.addr.do_send(
                         service::ProcessFailed(
@@ -301,9 +448,19 @@ impl Handler<ProcessMessage> for Process {
                     return
                 }
             }
+            ProcessMessage::StopSignal(step) => {
+                if let ProcessState::Stopping = self.state {
+                    // plain process does not close pipe on exit
+                    if kill(self.pid, None).is_ok() {
+                        self.stop_step(step, ctx);
+                    } else {
+                        ctx.stop();
+                    }
+                }
+            }
             ProcessMessage::StopTimeout => {
                 if let ProcessState::Stopping = self.state {
-                    info!("Worker shutdown timeout aftre {} secs", self.shutdown_timeout);
+                    info!("Worker shutdown timeout (pid:{})", self.pid);
                     self.addr.do_send(
                         service::ProcessFailed(
                             self.idx, self.pid, ProcessError::StopTimeout));
@@ -326,12 +483,56 @@ impl Handler<ProcessMessage> for Process {
                                 self.idx, self.pid, ProcessError::Heartbeat));
                     } else {
                         // send heartbeat to worker process and reset hearbeat timer
-                        self.framed.write(WorkerCommand::hb);
+                        self.send(WorkerCommand::hb);
                         ctx.notify_later(
                             ProcessMessage::Heartbeat, Duration::new(HEARTBEAT, 0));
                     }
                 }
             }
+            ProcessMessage::Readiness => {
+                if let (ProcessState::Probing, Some(probe)) = (&self.state, &self.readiness) {
+                    let interval = Duration::new(u64::from(probe.interval), 0);
+                    self.probe(probe)
+                        .into_actor(self)
+                        .then(move |res, act, ctx| {
+                            if let ProcessState::Probing = act.state {
+                                match res {
+                                    Ok(_) => act.running(ctx),
+                                    Err(err) => {
+                                        debug!("Readiness probe failed (pid:{}): {}", act.pid, err);
+                                        ctx.notify_later(ProcessMessage::Readiness, interval);
+                                    }
+                                }
+                            }
+                            actix::fut::ok(())
+                        }).spawn(ctx);
+                }
+            }
+            ProcessMessage::Liveness => {
+                if let (ProcessState::Running, Some(probe)) = (&self.state, &self.liveness) {
+                    let interval = Duration::new(u64::from(probe.interval), 0);
+                    let threshold = probe.threshold;
+                    self.probe(probe)
+                        .into_actor(self)
+                        .then(move |res, act, ctx| {
+                            match res {
+                                Ok(_) => act.probe_failures = 0,
+                                Err(err) => {
+                                    act.probe_failures += 1;
+                                    warn!("Liveness probe failed (pid:{}): {}", act.pid, err);
+                                    if act.probe_failures >= threshold {
+                                        act.addr.do_send(
+                                            service::ProcessFailed(
+                                                act.idx, act.pid, ProcessError::ProbeFailed(err)));
+                                        return actix::fut::ok(())
+                                    }
+                                }
+                            }
+                            ctx.notify_later(ProcessMessage::Liveness, interval);
+                            actix::fut::ok(())
+                        }).spawn(ctx);
+                }
+            }
             ProcessMessage::Kill => {
                 let _ = kill(self.pid, Signal::SIGKILL);
                 ctx.stop();
@@ -348,7 +549,7 @@ impl Handler<SendCommand> for Process {
     type Result = ();
 
     fn handle(&mut self, msg: SendCommand, _: &mut Context<Process>) {
-        self.framed.write(msg.0);
+        self.send(msg.0);
     }
 }
 
@@ -359,7 +560,7 @@ impl Handler<StartProcess> for Process {
     type Result = ();
 
     fn handle(&mut self, _: StartProcess, _: &mut Context<Process>) {
-        self.framed.write(WorkerCommand::start);
+        self.send(WorkerCommand::start);
     }
 }
 
@@ -370,7 +571,7 @@ impl Handler<PauseProcess> for Process {
     type Result = ();
 
     fn handle(&mut self, _: PauseProcess, _: &mut Context<Process>) {
-        self.framed.write(WorkerCommand::pause);
+        self.send(WorkerCommand::pause);
     }
 }
 
@@ -381,7 +582,7 @@ impl Handler<ResumeProcess> for Process {
     type Result = ();
 
     fn handle(&mut self, _: ResumeProcess, _: &mut Context<Process>) {
-        self.framed.write(WorkerCommand::resume);
+        self.send(WorkerCommand::resume);
     }
 }
 
@@ -398,11 +599,8 @@ impl Handler<StopProcess> for Process {
             ProcessState::Running => {
                 self.state = ProcessState::Stopping;
 
-                self.framed.write(WorkerCommand::stop);
-                ctx.notify_later(
-                    ProcessMessage::StopTimeout,
-                    Duration::new(self.shutdown_timeout, 0));
-                let _ = kill(self.pid, Signal::SIGTERM);
+                self.send(WorkerCommand::stop);
+                self.stop_step(0, ctx);
             },
             _ => {
                 let _ = kill(self.pid, Signal::SIGQUIT);
@@ -447,7 +645,13 @@ impl Decoder for TransportCodec {
         if src.len() >= size + 2 {
             src.split_to(2);
             let buf = src.split_to(size);
-            Ok(Some(ProcessMessage::Message(json::from_slice::<WorkerMessage>(&buf)?)))
+
+            // messages without data, i.e. `hb` without load
+            let mut msg: json::Value = json::from_slice(&buf)?;
+            if let Some(msg) = msg.as_object_mut() {
+                msg.entry("data").or_insert(json::Value::Null);
+            }
+            Ok(Some(ProcessMessage::Message(json::from_value::<WorkerMessage>(msg)?)))
         } else {
             Ok(None)
         }
@@ -469,3 +673,46 @@ impl Encoder for TransportCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Load;
+
+    fn frame(msg: &str) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(msg.len() + 2);
+        buf.put_u16::<BigEndian>(msg.len() as u16);
+        buf.put(msg);
+        buf
+    }
+
+    fn decode(msg: &str) -> ProcessMessage {
+        let mut buf = frame(msg);
+        let msg = TransportCodec.decode(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+        msg
+    }
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(r#"{"cmd":"loaded"}"#),
+                   ProcessMessage::Message(WorkerMessage::loaded));
+        assert_eq!(decode(r#"{"cmd":"hb"}"#),
+                   ProcessMessage::Message(WorkerMessage::hb(None)));
+        assert_eq!(decode(r#"{"cmd":"hb","data":{"inflight":3}}"#),
+                   ProcessMessage::Message(WorkerMessage::hb(
+                       Some(Load{inflight: 3, queue: 0}))));
+        assert_eq!(decode(r#"{"cmd":"cfgerror","data":"x"}"#),
+                   ProcessMessage::Message(WorkerMessage::cfgerror("x".to_owned())));
+    }
+
+    #[test]
+    fn test_decode_partial() {
+        let mut frame = frame(r#"{"cmd":"loaded"}"#);
+        let mut buf = frame.split_to(7);
+        assert!(TransportCodec.decode(&mut buf).unwrap().is_none());
+
+        buf.unsplit(frame);
+        assert_eq!(TransportCodec.decode(&mut buf).unwrap(),
+                   Some(ProcessMessage::Message(WorkerMessage::loaded)));
+    }
+}
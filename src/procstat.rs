@@ -0,0 +1,74 @@
+// Worker process statistics from `/proc`
+use std;
+use std::io::prelude::*;
+use std::path::Path;
+
+use libc;
+use nix::unistd::Pid;
+
+/// Process statistics
+pub struct ProcStat {
+    /// User and system cpu time in clock ticks
+    pub cpu_ticks: u64,
+    pub threads: u64,
+    /// Resident set size in bytes
+    pub rss: u64,
+    pub fds: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    /// Process uptime in seconds
+    pub uptime: u64,
+}
+
+/// Number of clock ticks per second
+pub fn clock_ticks() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// Read statistics of process, `None` if process does not exist
+pub fn read(pid: Pid) -> Option<ProcStat> {
+    let path = Path::new("/proc").join(pid.to_string());
+
+    // fields after command name, command name could contain spaces and parens
+    let stat = read_file(&path.join("stat"))?;
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |n: usize| fields.get(n - 3).and_then(|f| f.parse::<u64>().ok()).unwrap_or(0);
+
+    let ticks = clock_ticks();
+    let boot_uptime = read_file(Path::new("/proc/uptime"))
+        .and_then(|s| s.split_whitespace().next().and_then(|f| f.parse::<f64>().ok()))
+        .unwrap_or(0.0);
+    let uptime = (boot_uptime - field(22) as f64 / ticks as f64).max(0.0) as u64;
+
+    let rss = read_keyed(&path.join("status"), "VmRSS:").unwrap_or(0) * 1024;
+    let fds = std::fs::read_dir(path.join("fd")).map(|dir| dir.count() as u64).unwrap_or(0);
+
+    Some(ProcStat {
+        cpu_ticks: field(14) + field(15),
+        threads: field(20),
+        rss, fds, uptime,
+        read_bytes: read_keyed(&path.join("io"), "read_bytes:").unwrap_or(0),
+        write_bytes: read_keyed(&path.join("io"), "write_bytes:").unwrap_or(0),
+    })
+}
+
+fn read_file(path: &Path) -> Option<String> {
+    let mut content = String::new();
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content)).ok()?;
+    Some(content)
+}
+
+/// Read first number of line starting with `key`, like `VmRSS:  1024 kB`
+fn read_keyed(path: &Path, key: &str) -> Option<u64> {
+    read_file(path).and_then(|content| {
+        content.lines()
+            .filter(|line| line.starts_with(key))
+            .filter_map(|line| line[key.len()..].split_whitespace().next())
+            .filter_map(|val| val.parse().ok())
+            .next()
+    })
+}
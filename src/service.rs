@@ -1,18 +1,31 @@
 #![allow(dead_code)]
 
 use std;
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use nix::unistd::Pid;
 
 use actix::prelude::*;
 use actix::Response;
 use futures::Future;
 
-use event::{Event, Reason};
+use cgroup;
+use environ;
+use event::{Event, LogLine, OutputStream, ProcessStats, Reason, RevisionInfo,
+            ScalingEvent, ServiceStatus};
 use config::ServiceConfig;
+use output::ServiceLog;
 use worker::{Worker, WorkerMessage};
 use process::ProcessError;
 
+/// Interval of worker maintenance checks, in seconds
+const MAINTENANCE_INTERVAL: u64 = 5;
+
+/// Number of remembered changes of workers number
+const MAX_SCALING_EVENTS: usize = 20;
+
 /// Service state
 enum ServiceState {
     Running,
@@ -57,6 +70,12 @@ pub enum ServiceOperationError {
     Running,
     Stopped,
     Failed,
+    /// Revised configuration is not valid
+    Config(String),
+    /// No previous configuration revision
+    NoRevision,
+    /// Removed workers are still stopping
+    Scaling,
 }
 
 #[derive(Clone, Debug)]
@@ -69,15 +88,84 @@ pub enum StartStatus {
 #[derive(Clone, Debug)]
 pub enum ReloadStatus {
     Success,
-    Failed,
+    /// Reload failed, with reason if service keeps running old workers
+    Failed(Option<Reason>),
     Stopping,
 }
 
+/// Reload canary workers first and observe them before reloading the rest
+#[derive(Clone, Copy, Debug)]
+pub struct Canary {
+    /// Number of canary workers
+    pub workers: usize,
+    /// Observation period in seconds
+    pub observe: u64,
+}
+
+/// Service configuration revision
+pub struct Revision {
+    pub id: u32,
+    pub cfg: ServiceConfig,
+    pub timestamp: u64,
+    /// Changed settings
+    pub description: String,
+}
+
+impl Revision {
+    fn new(id: u32, cfg: ServiceConfig, description: String) -> Rc<Revision> {
+        Rc::new(Revision {
+            id, cfg, description,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        })
+    }
+}
+
 pub struct FeService {
     name: String,
+    cfg: ServiceConfig,
+    /// Remembered configuration revisions, current revision is last
+    revisions: VecDeque<Rc<Revision>>,
     state: ServiceState,
     paused: bool,
     workers: Vec<Worker>,
+    /// Workers removed by scale down, until they stop
+    retiring: Vec<Worker>,
+    logs: ServiceLog,
+    /// Number of output lines received
+    seq: u64,
+    tails: Vec<Subscriber>,
+    /// Number of automatic recovery attempts since service failed
+    recover_attempts: u16,
+    recover_handle: Option<SpawnHandle>,
+    rolling: Option<RollingReload>,
+    /// Total worker load samples within `autoscale.window`
+    load_samples: VecDeque<(Instant, u64)>,
+    /// Time of last change of workers number
+    scaled: Instant,
+    scaling: VecDeque<ScalingEvent>,
+}
+
+/// State of reload in batches of `reload_batch` workers
+struct RollingReload {
+    graceful: bool,
+    reason: Reason,
+    /// Revision workers get reloaded with, newer revisions apply on next reload
+    rev: Rc<Revision>,
+    size: usize,
+    /// Canary batch is not promoted yet
+    canary: Option<Canary>,
+    /// Workers of current batch, empty during pause between batches
+    batch: Vec<usize>,
+    /// Workers waiting for reload
+    pending: VecDeque<usize>,
+    /// Pause between batches or canary observation
+    timer: Option<SpawnHandle>,
+}
+
+/// Subscriber for worker output
+struct Subscriber {
+    worker: Option<usize>,
+    recipient: Recipient<Unsync, LogLines>,
 }
 
 impl FeService {
@@ -86,19 +174,47 @@ impl FeService {
     {
         FeService::create(move |ctx| {
             // create4 workers
+            let rev = Revision::new(1, cfg.clone(), "initial".to_owned());
             let mut workers = Vec::new();
             for idx in 0..num as usize {
-                workers.push(Worker::new(idx, cfg.clone(), ctx.address()));
+                workers.push(Worker::new(idx, rev.clone(), ctx.address()));
             }
 
             FeService {
                 name: cfg.name.clone(),
+                revisions: vec![rev].into_iter().collect(),
+                logs: ServiceLog::new(&cfg),
+                seq: 0,
+                tails: Vec::new(),
+                recover_attempts: 0,
+                recover_handle: None,
+                rolling: None,
+                load_samples: VecDeque::new(),
+                scaled: Instant::now(),
+                scaling: VecDeque::new(),
+                cfg,
                 state: ServiceState::Starting(actix::Condition::default()),
                 paused: false,
+                retiring: Vec::new(),
                 workers}
         })
     }
 
+    /// Last `lines` lines of workers output in order of arrival
+    fn recent_lines(&self, worker: Option<usize>, lines: usize) -> Result<Vec<LogLine>, ()> {
+        let mut recent: Vec<&(u64, LogLine)> = match worker {
+            Some(worker) => match self.workers.get(worker.wrapping_sub(1)) {
+                Some(worker) => worker.output.lines().iter().collect(),
+                None => return Err(()),
+            },
+            None => self.workers.iter()
+                .flat_map(|worker| worker.output.lines().iter()).collect(),
+        };
+        recent.sort_by_key(|item| item.0);
+        let skip = recent.len().saturating_sub(lines);
+        Ok(recent.into_iter().skip(skip).map(|item| item.1.clone()).collect())
+    }
+
     fn check_loading_workers(&mut self, restart_stopped: bool) -> (bool, bool) {
         let mut in_process = false;
         let mut failed = false;
@@ -121,8 +237,18 @@ impl FeService {
         (failed, in_process)
     }
 
+    /// Worker by index, including workers removed by scale down
+    fn worker_mut(&mut self, idx: usize) -> Option<&mut Worker> {
+        match self.workers.get_mut(idx) {
+            Some(worker) => Some(worker),
+            None => self.retiring.iter_mut().find(|worker| worker.idx == idx),
+        }
+    }
+
     // update internal state
-    fn update(&mut self) {
+    fn update(&mut self, ctx: &mut Context<Self>) {
+        self.retiring.retain(|worker| !worker.is_stopped());
+
         let state = std::mem::replace(&mut self.state, ServiceState::Failed);
 
         match state {
@@ -141,17 +267,23 @@ impl FeService {
                     } else {
                         task.set(StartStatus::Failed);
                         self.state = ServiceState::Failed;
+                        self.schedule_recovery(ctx);
                     }
                 } else {
                     if !in_process {
                         task.set(StartStatus::Success);
                         self.state = ServiceState::Running;
+                        self.recover_attempts = 0;
                     } else {
                         self.state = ServiceState::Starting(task);
                     }
                 }
             },
             ServiceState::Reloading(task) => {
+                if let Some(rolling) = self.rolling.take() {
+                    self.update_rolling(task, rolling, ctx);
+                    return
+                }
                 let (failed, in_process) = self.check_loading_workers(true);
 
                 // if we have failed workers, stop all and change service state to failed
@@ -164,13 +296,15 @@ impl FeService {
                         }
                         self.state = ServiceState::Reloading(task);
                     } else {
-                        task.set(ReloadStatus::Failed);
+                        task.set(ReloadStatus::Failed(None));
                         self.state = ServiceState::Failed;
+                        self.schedule_recovery(ctx);
                     }
                 } else {
                     if !in_process {
                         task.set(ReloadStatus::Success);
                         self.state = ServiceState::Running;
+                        self.recover_attempts = 0;
                     } else {
                         self.state = ServiceState::Reloading(task);
                     }
@@ -179,7 +313,8 @@ impl FeService {
             ServiceState::Stopping(task) => {
                 let (_, in_process) = self.check_loading_workers(false);
 
-                if !in_process {
+                // workers removed by scale down are still shutting down
+                if !in_process && self.retiring.is_empty() {
                     task.set(());
                     self.state = ServiceState::Stopped;
                 } else {
@@ -190,12 +325,343 @@ impl FeService {
         }
     }
 
+    /// Advance rolling reload once all workers of current batch are running,
+    /// failed batch aborts reload and leaves remaining workers untouched
+    fn update_rolling(&mut self, task: actix::Condition<ReloadStatus>,
+                      mut rolling: RollingReload, ctx: &mut Context<Self>) {
+        let mut failed = false;
+        let mut in_process = false;
+        for idx in &rolling.batch {
+            let worker = &self.workers[*idx];
+            if worker.is_failed() || worker.is_stopped() {
+                failed = true;
+            } else if !(worker.is_running() || worker.is_canary()) {
+                in_process = true;
+            }
+        }
+
+        if rolling.batch.is_empty() || in_process {
+            self.rolling = Some(rolling);
+            self.state = ServiceState::Reloading(task);
+        } else if failed {
+            // restore old processes of canary workers that are still observed
+            let reason = rolling.batch.iter()
+                .filter_map(|idx| self.workers[*idx].canary_failure().cloned()).next();
+            if let Some(timer) = rolling.timer.take() {
+                ctx.cancel_future(timer);
+            }
+            for idx in &rolling.batch {
+                self.workers[*idx].rollback(Reason::CanaryFailed);
+            }
+            error!("Reload of service {:?} failed, {} workers are not reloaded",
+                   self.name, rolling.pending.len());
+
+            // workers keep previous revision
+            if let Reason::Deploy = rolling.reason {
+                self.discard_revision(rolling.rev.id);
+            }
+            task.set(ReloadStatus::Failed(
+                Some(reason.unwrap_or(Reason::SomeWorkersFailed))));
+            self.state = ServiceState::Running;
+        } else if let Some(canary) = rolling.canary {
+            if rolling.timer.is_none() {
+                info!("Observing {} canary workers of service {:?} for {} seconds",
+                      rolling.batch.len(), self.name, canary.observe);
+                rolling.timer = Some(
+                    ctx.notify_later(Promote, Duration::new(canary.observe, 0)));
+            }
+            self.rolling = Some(rolling);
+            self.state = ServiceState::Reloading(task);
+        } else if rolling.pending.is_empty() {
+            task.set(ReloadStatus::Success);
+            self.state = ServiceState::Running;
+            self.recover_attempts = 0;
+        } else {
+            rolling.batch.clear();
+            let pause = self.cfg.reload_pause.unwrap_or(0);
+            if pause > 0 {
+                rolling.timer = Some(ctx.notify_later(NextBatch, Duration::new(pause, 0)));
+            }
+            self.rolling = Some(rolling);
+            self.state = ServiceState::Reloading(task);
+            if pause == 0 {
+                self.next_batch();
+            }
+        }
+    }
+
+    /// Reload next batch of workers
+    fn next_batch(&mut self) {
+        if let Some(ref mut rolling) = self.rolling {
+            rolling.timer = None;
+            let size = rolling.canary.map_or(rolling.size, |canary| canary.workers)
+                .min(rolling.pending.len());
+            rolling.batch = rolling.pending.drain(..size).collect();
+            debug!("Reloading {} workers of service {:?}, {} workers left",
+                   rolling.batch.len(), self.name, rolling.pending.len());
+            for idx in &rolling.batch {
+                let worker = &mut self.workers[*idx];
+                worker.set_revision(rolling.rev.clone());
+                if rolling.canary.is_some() {
+                    worker.reload_canary(rolling.reason.clone());
+                } else {
+                    worker.reload(rolling.graceful, rolling.reason.clone());
+                }
+            }
+        }
+    }
+
+    fn cancel_rolling(&mut self, ctx: &mut Context<Self>) {
+        if let Some(timer) = self.rolling.take().and_then(|rolling| rolling.timer) {
+            ctx.cancel_future(timer);
+        }
+    }
+
+    /// Retry failed service after `recover_after` delay, delay doubles with each attempt
+    fn schedule_recovery(&mut self, ctx: &mut Context<Self>) {
+        let secs = match self.cfg.recover_after {
+            Some(secs) => secs,
+            None => return,
+        };
+        if self.recover_attempts >= self.cfg.recover_attempts {
+            error!("Service {:?} failed, giving up after {} recovery attempts",
+                   self.name, self.recover_attempts);
+            return
+        }
+        let delay = secs.saturating_mul(1 << self.recover_attempts.min(16));
+        info!("Service {:?} failed, trying to recover in {} seconds", self.name, delay);
+
+        self.cancel_recovery(ctx);
+        self.recover_handle = Some(ctx.notify_later(Recover, Duration::new(delay, 0)));
+    }
+
+    fn cancel_recovery(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.recover_handle.take() {
+            ctx.cancel_future(handle);
+        }
+    }
+
+    fn start_workers(&mut self, reason: Reason) -> Response<StartStatus, ServiceOperationError> {
+        debug!("Starting service: {:?}", self.name);
+        let mut task = actix::Condition::default();
+        let rx = task.wait();
+        self.paused = false;
+        self.state = ServiceState::Starting(task);
+        for worker in &mut self.workers {
+            worker.reset_restarts();
+            worker.start(reason.clone());
+        }
+        Response::async(rx.map_err(|_| ServiceOperationError::Failed))
+    }
+
+    /// Current configuration revision
+    fn revision(&self) -> Rc<Revision> {
+        self.revisions.back().cloned().expect("current revision")
+    }
+
+    /// Use new configuration revision, remembers `revision_history` revisions
+    fn add_revision(&mut self, cfg: ServiceConfig, description: String) {
+        let id = self.revision().id + 1;
+        self.apply_config(cfg.clone());
+        self.revisions.push_back(Revision::new(id, cfg, description));
+        while self.revisions.len() > self.cfg.revision_history.max(1) as usize {
+            self.revisions.pop_front();
+        }
+    }
+
+    /// Forget failed revision, previous revision becomes current if it was current
+    fn discard_revision(&mut self, id: u32) {
+        if self.revisions.len() < 2 {
+            return
+        }
+        let current = self.revision().id == id;
+        if let Some(pos) = self.revisions.iter().position(|rev| rev.id == id) {
+            self.revisions.remove(pos);
+            info!("Revision {} of service {:?} is discarded", id, self.name);
+        }
+        if current {
+            let rev = self.revision();
+            self.apply_config(rev.cfg.clone());
+        }
+    }
+
+    /// Update service level settings, workers get configuration on reload
+    fn apply_config(&mut self, cfg: ServiceConfig) {
+        if self.cfg.stdout != cfg.stdout || self.cfg.stderr != cfg.stderr ||
+            self.cfg.directory != cfg.directory ||
+            self.cfg.log_max_size != cfg.log_max_size ||
+            self.cfg.log_max_age != cfg.log_max_age ||
+            self.cfg.log_backups != cfg.log_backups
+        {
+            self.logs = ServiceLog::new(&cfg);
+        }
+        self.cfg = cfg;
+        if let Err(err) = cgroup::configure(&self.cfg) {
+            error!("Service {:?}: {}", self.name, err);
+        }
+    }
+
+    /// Reload running service with current revision,
+    /// stopped or failed service uses it on next start
+    fn reload_revision(&mut self, reason: Reason, canary: Option<Canary>)
+                       -> Response<ReloadStatus, ServiceOperationError>
+    {
+        match self.state {
+            ServiceState::Running =>
+                self.reload(true, reason, canary),
+            _ => {
+                let rev = self.revision();
+                for worker in &mut self.workers {
+                    worker.set_revision(rev.clone());
+                }
+                match self.state {
+                    ServiceState::Failed | ServiceState::Stopped =>
+                        Response::reply(Ok(ReloadStatus::Success)),
+                    _ => Response::reply(Err(self.state.error()))
+                }
+            }
+        }
+    }
+
+    /// Add or remove workers, new workers start only if service is running
+    fn scale(&mut self, num: usize, reason: Reason, ctx: &mut Context<Self>) {
+        info!("Scaling service {:?} from {} to {} workers", self.name, self.workers.len(), num);
+        self.scaled = Instant::now();
+        if self.scaling.len() >= MAX_SCALING_EVENTS {
+            self.scaling.pop_front();
+        }
+        self.scaling.push_back(ScalingEvent {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            from: self.workers.len(),
+            to: num,
+            reason: reason.clone(),
+        });
+
+        // new workers get next indices, stopped service starts them on next start
+        let rev = self.revision();
+        while self.workers.len() < num {
+            let mut worker = Worker::new(self.workers.len(), rev.clone(), ctx.address());
+            if let ServiceState::Running = self.state {
+                worker.start(reason.clone());
+            }
+            self.workers.push(worker);
+        }
+
+        // highest indices stop first
+        while self.workers.len() > num {
+            if let Some(mut worker) = self.workers.pop() {
+                worker.stop(reason.clone());
+                self.retiring.push(worker);
+            }
+        }
+        self.update(ctx);
+    }
+
+    /// Sample load of workers, change number of workers to keep
+    /// moving average of load at target utilization
+    fn autoscale(&mut self, ctx: &mut Context<Self>) {
+        let cfg = match self.cfg.autoscale {
+            Some(cfg) => cfg,
+            None => return,
+        };
+        match self.state {
+            ServiceState::Running if !self.paused => (),
+            _ => {
+                self.load_samples.clear();
+                return
+            }
+        }
+
+        // workers without load reports are not sampled
+        let loads: Vec<u64> = self.workers.iter()
+            .filter_map(|worker| worker.load()).map(|load| load.total()).collect();
+        if loads.is_empty() {
+            return
+        }
+        let now = Instant::now();
+        let window = Duration::new(u64::from(cfg.window), 0);
+        self.load_samples.push_back((now, loads.iter().sum()));
+        while self.load_samples.front().is_some_and(|s| now.duration_since(s.0) > window) {
+            self.load_samples.pop_front();
+        }
+        let required = (u64::from(cfg.window) / MAINTENANCE_INTERVAL).max(1) as usize;
+        if self.load_samples.len() < required || self.rolling.is_some() ||
+            !self.retiring.is_empty() || self.workers.iter().any(|worker| worker.is_reloading())
+        {
+            return
+        }
+
+        let load = self.load_samples.iter().map(|s| s.1).sum::<u64>() as f64 /
+            self.load_samples.len() as f64;
+        let target = f64::from(cfg.capacity) * f64::from(cfg.target_utilization) / 100.0;
+        let num = ((load / target).ceil() as usize)
+            .max(cfg.min_workers as usize).min(cfg.max_workers as usize);
+        let current = self.workers.len();
+        let cooldown = if num > current {
+            cfg.scale_up_cooldown
+        } else if num < current {
+            cfg.scale_down_cooldown
+        } else {
+            return
+        };
+        if now.duration_since(self.scaled) < Duration::new(u64::from(cooldown), 0) {
+            return
+        }
+
+        let utilization = load / (current as f64 * f64::from(cfg.capacity)) * 100.0;
+        let decision = format!("Load {:.1}, utilization {:.0}%, target {}%",
+                               load, utilization, cfg.target_utilization);
+        info!("Autoscaling service {:?}: {}", self.name, decision);
+        self.scale(num, Reason::Autoscale(decision), ctx);
+    }
+
     fn message(&mut self, pid: Pid, message: WorkerMessage) {
         for worker in &mut self.workers {
             worker.message(pid, &message)
         }
     }
 
+    fn reload(&mut self, graceful: bool, reason: Reason, canary: Option<Canary>)
+              -> Response<ReloadStatus, ServiceOperationError>
+    {
+        debug!("Reloading service: {:?}", self.name);
+        let mut task = actix::Condition::default();
+        let rx = task.wait();
+
+        // running service reloads workers in batches, if configured,
+        // canary workers of graceful reload go first
+        let num = self.workers.len();
+        let (size, canary) = match self.state {
+            ServiceState::Running => (
+                self.cfg.reload_batch.map(|batch| batch.size(num)).filter(|size| *size < num),
+                canary.filter(|canary| graceful && canary.workers > 0)),
+            _ => (None, None),
+        };
+        self.paused = false;
+        self.state = ServiceState::Reloading(task);
+        match (size, canary) {
+            (None, None) => {
+                let rev = self.revision();
+                for worker in &mut self.workers {
+                    worker.set_revision(rev.clone());
+                    worker.reload(graceful, reason.clone());
+                }
+            }
+            (size, canary) => {
+                self.rolling = Some(RollingReload {
+                    graceful, reason, canary,
+                    rev: self.revision(),
+                    size: size.unwrap_or(num),
+                    batch: Vec::new(),
+                    pending: (0..num).collect(),
+                    timer: None,
+                });
+                self.next_batch();
+            }
+        }
+        Response::async(rx.map_err(|_| ServiceOperationError::Failed))
+    }
+
 }
 
 
@@ -203,7 +669,12 @@ impl Actor for FeService {
 
     type Context = Context<Self>;
 
-    fn started(&mut self, _: &mut Context<Self>) {
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        if let Err(err) = cgroup::configure(&self.cfg) {
+            error!("Service {:?}: {}", self.name, err);
+        }
+        ctx.notify_later(Maintenance, Duration::new(MAINTENANCE_INTERVAL, 0));
+
         // start workers
         for worker in &mut self.workers {
             worker.start(Reason::Initial);
@@ -217,9 +688,11 @@ pub struct ProcessMessage(pub usize, pub Pid, pub WorkerMessage);
 impl Handler<ProcessMessage> for FeService {
     type Result = ();
 
-    fn handle(&mut self, msg: ProcessMessage, _: &mut Context<Self>) {
-        self.workers[msg.0].message(msg.1, &msg.2);
-        self.update();
+    fn handle(&mut self, msg: ProcessMessage, ctx: &mut Context<Self>) {
+        if let Some(worker) = self.worker_mut(msg.0) {
+            worker.message(msg.1, &msg.2);
+        }
+        self.update(ctx);
     }
 }
 
@@ -230,11 +703,88 @@ impl Handler<ProcessFailed> for FeService {
     type Result = ();
 
     fn handle(&mut self, msg: ProcessFailed, ctx: &mut Context<Self>) {
-        // TODO: delay failure processing, needs better approach
-        ctx.run_later(Duration::new(5, 0), move |act, _| {
-            act.workers[msg.0].exited(msg.1, &msg.2);
-            act.update();
-        });
+        if let Some(worker) = self.worker_mut(msg.0) {
+            worker.exited(msg.1, &msg.2);
+        }
+        self.update(ctx);
+    }
+}
+
+/// Periodic check of worker lifetime and resource limits
+#[derive(Message)]
+struct Maintenance;
+
+impl Handler<Maintenance> for FeService {
+    type Result = ();
+
+    fn handle(&mut self, _: Maintenance, ctx: &mut Context<Self>) {
+        ctx.notify_later(Maintenance, Duration::new(MAINTENANCE_INTERVAL, 0));
+        self.autoscale(ctx);
+
+        // recycle one worker at a time
+        if let ServiceState::Running = self.state {
+            if self.paused || self.workers.iter().any(|worker| worker.is_reloading()) {
+                return
+            }
+            for worker in &mut self.workers {
+                if let Some(reason) = worker.exceeded_limits() {
+                    worker.reload(true, reason);
+                    break
+                }
+            }
+        }
+    }
+}
+
+/// Pause between reload batches expired
+#[derive(Message)]
+struct NextBatch;
+
+impl Handler<NextBatch> for FeService {
+    type Result = ();
+
+    fn handle(&mut self, _: NextBatch, ctx: &mut Context<Self>) {
+        let waiting = self.rolling.as_ref().is_some_and(|rolling| rolling.batch.is_empty());
+        if waiting && matches!(self.state, ServiceState::Reloading(_)) {
+            self.next_batch();
+            self.update(ctx);
+        }
+    }
+}
+
+/// Canary observation period expired
+#[derive(Message)]
+struct Promote;
+
+impl Handler<Promote> for FeService {
+    type Result = ();
+
+    fn handle(&mut self, _: Promote, ctx: &mut Context<Self>) {
+        if let Some(ref mut rolling) = self.rolling {
+            if rolling.canary.take().is_some() {
+                info!("Canary workers of service {:?} passed observation", self.name);
+                rolling.timer = None;
+                for idx in &rolling.batch {
+                    self.workers[*idx].promote();
+                }
+            }
+        }
+        self.update(ctx);
+    }
+}
+
+/// Restart delay of the worker expired
+#[derive(Message)]
+pub struct BackoffExpired(pub usize, pub usize);
+
+impl Handler<BackoffExpired> for FeService {
+    type Result = ();
+
+    fn handle(&mut self, msg: BackoffExpired, ctx: &mut Context<Self>) {
+        if let Some(worker) = self.worker_mut(msg.0) {
+            worker.backoff_expired(msg.1);
+        }
+        self.update(ctx);
     }
 }
 
@@ -244,9 +794,11 @@ pub struct ProcessLoaded(pub usize, pub Pid);
 impl Handler<ProcessLoaded> for FeService {
     type Result = ();
 
-    fn handle(&mut self, msg: ProcessLoaded, _: &mut Context<Self>) {
-        self.workers[msg.0].loaded(msg.1);
-        self.update();
+    fn handle(&mut self, msg: ProcessLoaded, ctx: &mut Context<Self>) {
+        if let Some(worker) = self.worker_mut(msg.0) {
+            worker.loaded(msg.1);
+        }
+        self.update(ctx);
     }
 }
 
@@ -256,11 +808,103 @@ pub struct ProcessExited(pub Pid, pub ProcessError);
 impl Handler<ProcessExited> for FeService {
     type Result = ();
 
-    fn handle(&mut self, msg: ProcessExited, _: &mut Context<Self>) {
-        for worker in &mut self.workers {
-            worker.exited(msg.0, &msg.1);
+    fn handle(&mut self, msg: ProcessExited, ctx: &mut Context<Self>) {
+        let err = cgroup::exited(&self.cfg, msg.0, msg.1);
+        for worker in self.workers.iter_mut().chain(self.retiring.iter_mut()) {
+            worker.exited(msg.0, &err);
         }
-        self.update();
+        self.update(ctx);
+    }
+}
+
+#[derive(Message)]
+pub struct ProcessOutput(pub usize, pub Pid, pub OutputStream, pub String);
+
+impl Handler<ProcessOutput> for FeService {
+    type Result = ();
+
+    fn handle(&mut self, msg: ProcessOutput, _: &mut Context<Self>) {
+        self.logs.write(&self.name, msg.0, msg.1, msg.2, &msg.3);
+
+        let line = LogLine {
+            worker: msg.0 + 1,
+            pid: format!("{}", msg.1),
+            stream: msg.2,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            line: msg.3,
+        };
+        // forget subscribers of disconnected clients
+        self.tails.retain(|tail| match tail.worker {
+            Some(worker) if worker != line.worker => true,
+            _ => tail.recipient.do_send(LogLines(vec![line.clone()])).is_ok(),
+        });
+
+        self.seq += 1;
+        let seq = self.seq;
+        if let Some(worker) = self.worker_mut(msg.0) {
+            worker.add_output(seq, line);
+        }
+    }
+}
+
+/// Worker output lines
+pub struct LogLines(pub Vec<LogLine>);
+
+impl Message for LogLines {
+    type Result = ();
+}
+
+/// Recent output of service workers, all workers if worker number is not set
+pub struct Logs {
+    pub worker: Option<usize>,
+    pub lines: usize,
+}
+
+impl Message for Logs {
+    type Result = Result<Vec<LogLine>, ()>;
+}
+
+impl Handler<Logs> for FeService {
+    type Result = Result<Vec<LogLine>, ()>;
+
+    fn handle(&mut self, msg: Logs, _: &mut Context<Self>) -> Self::Result {
+        self.recent_lines(msg.worker, msg.lines)
+    }
+}
+
+/// Subscribe to worker output, recipient receives recent lines
+/// and then new lines until it gets disconnected
+pub struct Tail {
+    pub worker: Option<usize>,
+    pub lines: usize,
+    pub recipient: Recipient<Unsync, LogLines>,
+}
+
+impl Message for Tail {
+    type Result = Result<(), ()>;
+}
+
+impl Handler<Tail> for FeService {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, msg: Tail, _: &mut Context<Self>) -> Self::Result {
+        let lines = self.recent_lines(msg.worker, msg.lines)?;
+        if msg.recipient.do_send(LogLines(lines)).is_ok() {
+            self.tails.push(Subscriber{worker: msg.worker, recipient: msg.recipient});
+        }
+        Ok(())
+    }
+}
+
+/// Reopen service log files
+#[derive(Message)]
+pub struct ReopenLogs;
+
+impl Handler<ReopenLogs> for FeService {
+    type Result = ();
+
+    fn handle(&mut self, _: ReopenLogs, _: &mut Context<Self>) {
+        self.logs.reopen();
     }
 }
 
@@ -276,7 +920,7 @@ impl Handler<Pids> for FeService {
 
     fn handle(&mut self, _: Pids, _: &mut Context<Self>) -> Self::Result {
         let mut pids = Vec::new();
-        for worker in &self.workers {
+        for worker in self.workers.iter().chain(self.retiring.iter()) {
             if let Some(pid) = worker.pid() {
                 pids.push(format!("{}", pid));
             }
@@ -289,24 +933,50 @@ impl Handler<Pids> for FeService {
 pub struct Status;
 
 impl Message for Status {
-    type Result = Result<(String, Vec<(String, Vec<Event>)>), ()>;
+    type Result = Result<ServiceStatus, ()>;
 }
 
 impl Handler<Status> for FeService {
-    type Result = Result<(String, Vec<(String, Vec<Event>)>), ()>;
+    type Result = Result<ServiceStatus, ()>;
 
     fn handle(&mut self, _: Status, _: &mut Context<Self>) -> Self::Result {
         let mut events: Vec<(String, Vec<Event>)> = Vec::new();
-        for worker in &self.workers {
+        for worker in self.workers.iter().chain(self.retiring.iter()) {
             events.push(
                 (format!("worker({})", worker.idx + 1), Vec::from(&worker.events)));
         }
 
+        let stats: Vec<ProcessStats> = self.workers.iter_mut()
+            .chain(self.retiring.iter_mut())
+            .filter_map(|worker| worker.stats()).collect();
+        let total = if stats.is_empty() {
+            None
+        } else {
+            let mut total = ProcessStats::default();
+            for item in &stats {
+                total.add(item);
+            }
+            Some(total)
+        };
+
         let status = match self.state {
             ServiceState::Running => if self.paused { "paused" } else { "running" }
             _ => self.state.description()
         };
-        Ok((status.to_owned(), events))
+        Ok(ServiceStatus {
+            status: status.to_owned(),
+            workers: events,
+            environ: environ::redact(&self.cfg.env),
+            usage: cgroup::usage(&self.cfg),
+            stats, total,
+            revisions: self.revisions.iter()
+                .map(|rev| RevisionInfo {
+                    id: rev.id,
+                    timestamp: rev.timestamp,
+                    description: rev.description.clone(),
+                }).collect(),
+            scaling: self.scaling.iter().cloned().collect(),
+        })
     }
 }
 
@@ -320,28 +990,40 @@ impl Message for Start {
 impl Handler<Start> for FeService {
     type Result = Response<StartStatus, ServiceOperationError>;
 
-    fn handle(&mut self, _: Start, _: &mut Context<Self>) -> Self::Result
+    fn handle(&mut self, _: Start, ctx: &mut Context<Self>) -> Self::Result
     {
         match self.state {
             ServiceState::Starting(ref mut task) => {
                 Response::async(task.wait().map_err(|_| ServiceOperationError::Failed))
             }
             ServiceState::Failed | ServiceState::Stopped => {
-                debug!("Starting service: {:?}", self.name);
-                let mut task = actix::Condition::default();
-                let rx = task.wait();
-                self.paused = false;
-                self.state = ServiceState::Starting(task);
-                for worker in &mut self.workers {
-                    worker.start(Reason::ConsoleRequest);
-                }
-                Response::async(rx.map_err(|_| ServiceOperationError::Failed))
+                self.cancel_recovery(ctx);
+                self.recover_attempts = 0;
+                self.start_workers(Reason::ConsoleRequest)
             }
             _ => Response::reply(Err(self.state.error()))
         }
     }
 }
 
+/// Automatic recovery of failed service
+#[derive(Message)]
+struct Recover;
+
+impl Handler<Recover> for FeService {
+    type Result = ();
+
+    fn handle(&mut self, _: Recover, _: &mut Context<Self>) {
+        self.recover_handle = None;
+        if let ServiceState::Failed = self.state {
+            self.recover_attempts += 1;
+            info!("Recovering failed service {:?}, attempt {} of {}",
+                  self.name, self.recover_attempts, self.cfg.recover_attempts);
+            let _ = self.start_workers(Reason::AutoRecovery);
+        }
+    }
+}
+
 /// Pause service command
 pub struct Pause;
 
@@ -393,8 +1075,8 @@ impl Handler<Resume> for FeService {
     }
 }
 
-/// Reload service
-pub struct Reload(pub bool);
+/// Reload service, graceful flag and optional canary
+pub struct Reload(pub bool, pub Option<Canary>);
 
 impl Message for Reload {
     type Result = Result<ReloadStatus, ServiceOperationError>;
@@ -403,27 +1085,138 @@ impl Message for Reload {
 impl Handler<Reload> for FeService {
     type Result = Response<ReloadStatus, ServiceOperationError>;
 
-    fn handle(&mut self, msg: Reload, _: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: Reload, ctx: &mut Context<Self>) -> Self::Result {
         match self.state {
             ServiceState::Reloading(ref mut task) => {
                 Response::async(task.wait().map_err(|_| ServiceOperationError::Failed))
             }
             ServiceState::Running | ServiceState::Failed | ServiceState::Stopped => {
-                debug!("Reloading service: {:?}", self.name);
-                let mut task = actix::Condition::default();
-                let rx = task.wait();
-                self.paused = false;
-                self.state = ServiceState::Reloading(task);
-                for worker in &mut self.workers {
-                    worker.reload(msg.0, Reason::ConsoleRequest);
-                }
-                Response::async(rx.map_err(|_| ServiceOperationError::Failed))
+                self.cancel_recovery(ctx);
+                self.reload(msg.0, Reason::ConsoleRequest, msg.1)
             }
             _ => Response::reply(Err(self.state.error()))
         }
     }
 }
 
+/// Update service configuration
+///
+/// Running service gets gracefully reloaded, stopped or failed service
+/// uses new configuration on next start.
+pub struct Configure(pub ServiceConfig);
+
+impl Message for Configure {
+    type Result = Result<ReloadStatus, ServiceOperationError>;
+}
+
+impl Handler<Configure> for FeService {
+    type Result = Response<ReloadStatus, ServiceOperationError>;
+
+    fn handle(&mut self, msg: Configure, _: &mut Context<Self>) -> Self::Result {
+        debug!("Updating service configuration: {:?}", self.name);
+        self.add_revision(msg.0, "configuration file".to_owned());
+        self.reload_revision(Reason::ConfigReload, None)
+    }
+}
+
+/// Deploy new revision of service configuration
+pub struct Deploy {
+    /// `key=value` settings
+    pub settings: Vec<(String, String)>,
+    /// Master working directory
+    pub directory: PathBuf,
+    pub canary: Option<Canary>,
+}
+
+impl Message for Deploy {
+    type Result = Result<ReloadStatus, ServiceOperationError>;
+}
+
+impl Handler<Deploy> for FeService {
+    type Result = Response<ReloadStatus, ServiceOperationError>;
+
+    fn handle(&mut self, msg: Deploy, ctx: &mut Context<Self>) -> Self::Result {
+        match self.state {
+            ServiceState::Running | ServiceState::Failed | ServiceState::Stopped => (),
+            _ => return Response::reply(Err(self.state.error())),
+        }
+        let cfg = match self.revision().cfg.revise(&msg.directory, &msg.settings) {
+            Ok(cfg) => cfg,
+            Err(err) => return Response::reply(Err(ServiceOperationError::Config(err))),
+        };
+        let description = msg.settings.iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>().join(" ");
+        info!("Deploying service {:?}: {}", self.name, description);
+
+        self.cancel_recovery(ctx);
+        self.add_revision(cfg, description);
+        self.reload_revision(Reason::Deploy, msg.canary)
+    }
+}
+
+/// Gracefully reload service with previous configuration revision
+pub struct Rollback;
+
+impl Message for Rollback {
+    type Result = Result<ReloadStatus, ServiceOperationError>;
+}
+
+impl Handler<Rollback> for FeService {
+    type Result = Response<ReloadStatus, ServiceOperationError>;
+
+    fn handle(&mut self, _: Rollback, ctx: &mut Context<Self>) -> Self::Result {
+        match self.state {
+            ServiceState::Running | ServiceState::Failed | ServiceState::Stopped => (),
+            _ => return Response::reply(Err(self.state.error())),
+        }
+        if self.revisions.len() < 2 {
+            return Response::reply(Err(ServiceOperationError::NoRevision))
+        }
+        self.revisions.pop_back();
+        let rev = self.revision();
+        info!("Rolling back service {:?} to revision {}", self.name, rev.id);
+
+        self.cancel_recovery(ctx);
+        self.apply_config(rev.cfg.clone());
+        self.reload_revision(Reason::Rollback, None)
+    }
+}
+
+/// Change number of service workers
+pub struct Scale(pub usize);
+
+impl Message for Scale {
+    type Result = Result<(), ServiceOperationError>;
+}
+
+impl Handler<Scale> for FeService {
+    type Result = Result<(), ServiceOperationError>;
+
+    fn handle(&mut self, msg: Scale, ctx: &mut Context<Self>) -> Self::Result {
+        match self.state {
+            ServiceState::Running | ServiceState::Failed | ServiceState::Stopped => (),
+            _ => return Err(self.state.error()),
+        }
+        if msg.0 == 0 {
+            return Err(ServiceOperationError::Config(
+                "Number of workers must be greater than 0".to_owned()))
+        }
+        if let Some(autoscale) = self.cfg.autoscale {
+            if msg.0 < autoscale.min_workers as usize || msg.0 > autoscale.max_workers as usize {
+                return Err(ServiceOperationError::Config(format!(
+                    "Number of workers must be in {}..{} range of autoscale settings",
+                    autoscale.min_workers, autoscale.max_workers)))
+            }
+        }
+        if !self.retiring.is_empty() {
+            return Err(ServiceOperationError::Scaling)
+        }
+        self.scale(msg.0, Reason::Scale, ctx);
+        Ok(())
+    }
+}
+
 /// Stop service command
 pub struct Stop(pub bool, pub Reason);
 
@@ -434,8 +1227,10 @@ impl Message for Stop {
 impl Handler<Stop> for FeService {
     type Result = Response<(), ()>;
 
-    fn handle(&mut self, msg: Stop, _: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: Stop, ctx: &mut Context<Self>) -> Self::Result {
         let state = std::mem::replace(&mut self.state, ServiceState::Stopped);
+        self.cancel_recovery(ctx);
+        self.cancel_rolling(ctx);
 
         match state {
             ServiceState::Failed | ServiceState::Stopped => {
@@ -468,7 +1263,13 @@ impl Handler<Stop> for FeService {
                 worker.quit(msg.1.clone());
             }
         }
-        self.update();
+        // retiring workers are stopping already, force them on quit
+        if !msg.0 {
+            for worker in &mut self.retiring {
+                worker.quit(msg.1.clone());
+            }
+        }
+        self.update(ctx);
 
         Response::async(rx.map(|_| ()).map_err(|_| ()))
     }
@@ -2,127 +2,250 @@ use std;
 use std::io;
 use std::net::TcpListener;
 use std::error::Error;
-use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
 
+use libc;
 use serde_json as json;
 use net2::TcpBuilder;
 use net2::unix::UnixTcpBuilderExt;
-use nix::fcntl::{fcntl, FcntlArg, FdFlag, FD_CLOEXEC};
+use nix::unistd::chown;
 
 use addrinfo;
 use config::{Proto, SocketConfig};
 
 
+/// Listening socket
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Listener::Tcp(ref lst) => lst.as_raw_fd(),
+            Listener::Unix(ref lst, _) => lst.as_raw_fd(),
+        }
+    }
+}
+
 pub struct Socket {
     pub name: String,
-    pub listener: TcpListener,
-    pub info: addrinfo::AddrInfo,
+    pub listener: Listener,
+    pub cfg: SocketConfig,
+    /// `FECTL_FD_*`, `FECTL_APP_*` and `FECTL_ARGS_*` variables for workers
+    env: Vec<(String, String)>,
+}
+
+/// Listener inherited by service workers
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceSocket {
+    pub fd: RawFd,
+    pub env: Vec<(String, String)>,
 }
 
 
 impl Socket {
 
-    fn new(name: String, listener: TcpListener,
-           info: addrinfo::AddrInfo, cfg: &SocketConfig) -> Socket {
+    fn new(name: String, listener: Listener, family: addrinfo::Family,
+           socktype: addrinfo::SocketType, protocol: addrinfo::Protocol,
+           cfg: &SocketConfig) -> Socket {
         let fd = listener.as_raw_fd();
-        std::env::set_var(format!("FECTL_FD_{}", name),
-                          format!("{},FAMILY:{},SOCKETTYPE:{},PROTO:{}",
-                                  fd.to_string(),
-                                  info.family.to_int(),
-                                  info.socktype.to_int(),
-                                  info.protocol.to_int()));
+        let mut env = vec![
+            (format!("FECTL_FD_{}", name),
+             format!("{},FAMILY:{},SOCKETTYPE:{},PROTO:{}",
+                     fd.to_string(),
+                     family.to_int(),
+                     socktype.to_int(),
+                     protocol.to_int()))];
         // loader
         if let Some(ref app) = cfg.app {
-            std::env::set_var(format!("FECTL_APP_{}", name), app);
+            env.push((format!("FECTL_APP_{}", name), app.clone()));
 
             // encode arguments
             if !cfg.arguments.is_empty() {
                 let args = json::to_string(&cfg.arguments).unwrap();
-                std::env::set_var(format!("FECTL_ARGS_{}", name), args);
+                env.push((format!("FECTL_ARGS_{}", name), args));
             }
         }
 
-        let mut flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD).unwrap());
-        flags.remove(FD_CLOEXEC);
-        let _ = fcntl(fd, FcntlArg::F_SETFD(flags));
-
+        // listener stays close-on-exec, worker process passes only
+        // sockets of its service
         Socket {
             name,
             listener,
-            info,
+            cfg: cfg.clone(),
+            env,
         }
     }
 
+    /// Check if service is allowed to use this socket
+    pub fn is_scoped(&self, service: &str) -> bool {
+        self.cfg.service.is_empty() || self.cfg.service.iter().any(|s| s == service)
+    }
+
+    /// Listeners available for service
+    pub fn service_sockets(sockets: &[Socket], service: &str) -> Vec<ServiceSocket> {
+        sockets.iter()
+            .filter(|sock| sock.is_scoped(service))
+            .map(|sock| ServiceSocket {
+                fd: sock.listener.as_raw_fd(),
+                env: sock.env.clone(),
+            }).collect()
+    }
+
     pub fn load_config(cfg: &[SocketConfig]) -> Result<Vec<Socket>, std::io::Error>
     {
         let mut services = Vec::new();
 
         for sock in cfg.iter() {
-            // resolve addresses
-            let lookup = addrinfo::lookup_addrinfo(
-                sock.host.clone(), Some(sock.port.to_string()), 0,
-                addrinfo::AI_PASSIVE, addrinfo::SocketType::Stream)?;
-            let addrs: Vec<addrinfo::AddrInfo> = lookup.collect();
-            if addrs.is_empty() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other, "getaddrinfo() returned empty list"))
-            }
+            services.push(Socket::bind(sock)?);
+        }
+        Ok(services)
+    }
 
-            // start listen
-            let mut found = false;
-            for addr in addrs {
-                let builder = match addr.family {
-                    addrinfo::Family::Inet => {
-                        if sock.proto == Proto::tcp6 {
-                            continue
-                        }
-                        if let Ok(b) = TcpBuilder::new_v4() {
-                            b
-                        } else {
-                            continue
-                        }
+    /// Start listener for socket configuration
+    pub fn bind(sock: &SocketConfig) -> Result<Socket, std::io::Error> {
+        if sock.proto == Proto::unix {
+            return Socket::bind_unix(sock)
+        }
+
+        let port = match sock.port {
+            Some(port) => port,
+            None => return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Port is required for `{}` socket", sock.name))),
+        };
+
+        // resolve addresses
+        let lookup = addrinfo::lookup_addrinfo(
+            sock.host.clone(), Some(port.to_string()), 0,
+            addrinfo::AI_PASSIVE, addrinfo::SocketType::Stream)?;
+        let addrs: Vec<addrinfo::AddrInfo> = lookup.collect();
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other, "getaddrinfo() returned empty list"))
+        }
+
+        // start listen
+        for addr in addrs {
+            let builder = match addr.family {
+                addrinfo::Family::Inet => {
+                    if sock.proto == Proto::tcp6 {
+                        continue
                     }
-                    addrinfo::Family::Inet6 => {
-                        if sock.proto == Proto::tcp4 {
-                            continue
-                        }
-                        if let Ok(b) = TcpBuilder::new_v6() {
-                            let _ = b.only_v6(true);
-                            b
-                        } else {
-                            continue
-                        }
-                    },
-                    _ => continue
-                };
-
-                let _ = builder.reuse_address(true);
-                let _ = builder.reuse_port(true);
-
-                match builder.bind(addr.sockaddr) {
-                    Ok(_) => {
-                        if let Ok(lst) = builder.listen(i32::from(sock.backlog)) {
-                            info!("Init listener on {:?}", addr.sockaddr);
-                            let mut addr = addr.clone();
-                            addr.sockaddr = lst.local_addr().expect("should not fail");
-                            services.push(Socket::new(sock.name.clone(), lst, addr, sock));
-                            found = true;
-                            break;
-                        }
-                    },
-                    Err(err) => {
-                        println!("Can not bind to address: \"{}\" {:?}",
-                                 addr.sockaddr, err.description());
+                    if let Ok(b) = TcpBuilder::new_v4() {
+                        b
+                    } else {
+                        continue
                     }
                 }
+                addrinfo::Family::Inet6 => {
+                    if sock.proto == Proto::tcp4 {
+                        continue
+                    }
+                    if let Ok(b) = TcpBuilder::new_v6() {
+                        let _ = b.only_v6(true);
+                        b
+                    } else {
+                        continue
+                    }
+                },
+                _ => continue
+            };
+
+            let _ = builder.reuse_address(true);
+            let _ = builder.reuse_port(true);
+
+            match builder.bind(addr.sockaddr) {
+                Ok(_) => {
+                    if let Ok(lst) = builder.listen(i32::from(sock.backlog)) {
+                        info!("Init listener on {:?}", addr.sockaddr);
+                        return Ok(Socket::new(
+                            sock.name.clone(), Listener::Tcp(lst),
+                            addr.family, addr.socktype, addr.protocol, sock))
+                    }
+                },
+                Err(err) => {
+                    println!("Can not bind to address: \"{}\" {:?}",
+                             addr.sockaddr, err.description());
+                }
             }
-            if !found {
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Can not start listener for `{}` service", sock.name)))
+    }
+
+    fn bind_unix(sock: &SocketConfig) -> Result<Socket, io::Error> {
+        let path = match sock.path {
+            Some(ref path) => PathBuf::from(path),
+            None => return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Path is required for `{}` unix socket", sock.name))),
+        };
+        Socket::remove_stale(&path)?;
+
+        let lst = UnixListener::bind(&path).map_err(|err| io::Error::new(
+            err.kind(), format!("Can not bind to unix socket {:?}: {}", path, err)))?;
+
+        // std listener uses default backlog
+        if unsafe { libc::listen(lst.as_raw_fd(), i32::from(sock.backlog)) } != 0 {
+            let _ = std::fs::remove_file(&path);
+            return Err(io::Error::last_os_error())
+        }
+
+        if let Some(mode) = sock.mode {
+            if let Err(err) = std::fs::set_permissions(
+                &path, std::fs::Permissions::from_mode(mode))
+            {
+                let _ = std::fs::remove_file(&path);
+                return Err(io::Error::new(
+                    err.kind(), format!("Can not set mode of {:?}: {}", path, err)))
+            }
+        }
+        if sock.owner.is_some() || sock.group.is_some() {
+            if let Err(err) = chown(&path, sock.owner, sock.group) {
+                let _ = std::fs::remove_file(&path);
                 return Err(io::Error::new(
                     io::ErrorKind::Other,
-                    format!("Can not start listener for `{}` service", sock.name)))
+                    format!("Can not change owner of {:?}: {}", path, err)))
             }
         }
-        Ok(services)
+
+        info!("Init listener on {:?}", path);
+        Ok(Socket::new(sock.name.clone(), Listener::Unix(lst, path),
+                       addrinfo::Family::Unix, addrinfo::SocketType::Stream,
+                       addrinfo::Protocol::Unspec, sock))
+    }
+
+    /// Remove socket file left by previous master process.
+    ///
+    /// Only socket files without listener get removed.
+    fn remove_stale(path: &Path) -> Result<(), io::Error> {
+        let meta = match std::fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Can not bind to {:?}: file exists and is not a socket", path)))
+        }
+        match UnixStream::connect(path) {
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Can not bind to {:?}: socket is in use", path))),
+            Err(ref err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                info!("Removing stale socket file {:?}", path);
+                std::fs::remove_file(path)
+            }
+            Err(err) => Err(err),
+        }
     }
 }
 
@@ -130,6 +253,8 @@ impl Socket {
 impl Drop for Socket {
 
     fn drop(&mut self) {
-        std::env::remove_var(format!("FD_{}", self.name));
+        if let Listener::Unix(_, ref path) = self.listener {
+            let _ = std::fs::remove_file(path);
+        }
     }
 }
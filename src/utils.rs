@@ -1,6 +1,9 @@
+use std;
 use std::env;
 use std::path::Path;
 use std::ffi::CString;
+use std::hash::{BuildHasher, Hasher};
+use std::collections::hash_map::RandomState;
 
 use nix::unistd::Pid;
 
@@ -25,6 +28,75 @@ pub(crate) fn find_path(name: &str) -> Option<String>
     })
 }
 
+/// Split command line into words with POSIX shell quoting rules.
+///
+/// Single quotes preserve literal value of characters, double quotes
+/// allow `\` escapes of `"`, `\`, `$` and `` ` `` characters.
+pub fn split_command(cmd: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = cmd.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            ' ' | '\t' | '\n' => if in_word {
+                words.push(std::mem::take(&mut word));
+                in_word = false;
+            },
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(ch) => word.push(ch),
+                        None => return Err("unterminated single quote".to_owned()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(ch @ '"') | Some(ch @ '\\') |
+                            Some(ch @ '$') | Some(ch @ '`') => word.push(ch),
+                            Some('\n') => (),
+                            Some(ch) => {
+                                word.push('\\');
+                                word.push(ch);
+                            }
+                            None => return Err("unterminated double quote".to_owned()),
+                        },
+                        Some(ch) => word.push(ch),
+                        None => return Err("unterminated double quote".to_owned()),
+                    }
+                }
+            }
+            '\\' => {
+                // line continuation does not start a word
+                match chars.next() {
+                    Some('\n') => (),
+                    Some(ch) => {
+                        in_word = true;
+                        word.push(ch);
+                    }
+                    None => return Err("trailing backslash".to_owned()),
+                }
+            }
+            ch => {
+                in_word = true;
+                word.push(ch);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
 pub fn get_env_vars(all: bool) -> Vec<CString> {
     let mut env = Vec::new();
     for (k, v) in env::vars() {
@@ -41,3 +113,54 @@ pub fn get_env_vars(all: bool) -> Vec<CString> {
 pub fn str(pid: Pid) -> Option<String> {
     Some(format!("{}", pid))
 }
+
+/// Random number in 0..1 range, not suitable for cryptography
+pub fn random() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(std::time::SystemTime::now()
+                     .duration_since(std::time::UNIX_EPOCH)
+                     .map(|d| u64::from(d.subsec_nanos()))
+                     .unwrap_or(0));
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(cmd: &str) -> Vec<String> {
+        split_command(cmd).unwrap()
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split("app  --port 8080\t-v\n"), vec!["app", "--port", "8080", "-v"]);
+        assert!(split(" ").is_empty());
+    }
+
+    #[test]
+    fn test_split_command_quotes() {
+        assert_eq!(split("app --title 'my app'"), vec!["app", "--title", "my app"]);
+        assert_eq!(split(r#"app "a b" 'c "d"' "e 'f'""#), vec!["app", "a b", "c \"d\"", "e 'f'"]);
+        assert_eq!(split("app x'y z'\"w\""), vec!["app", "xy zw"]);
+        assert_eq!(split("app '' \"\""), vec!["app", "", ""]);
+        assert_eq!(split(r#"app '\$HOME'"#), vec!["app", "\\$HOME"]);
+    }
+
+    #[test]
+    fn test_split_command_escapes() {
+        assert_eq!(split(r"app a\ b \'c"), vec!["app", "a b", "'c"]);
+        assert_eq!(split(r#"app "\"q\" \\ \$ \` \n""#), vec!["app", "\"q\" \\ $ ` \\n"]);
+        assert_eq!(split("app \\\n--flag"), vec!["app", "--flag"]);
+        assert_eq!(split("app \\\n  --flag"), vec!["app", "--flag"]);
+        assert_eq!(split("app --a\\\nb"), vec!["app", "--ab"]);
+    }
+
+    #[test]
+    fn test_split_command_errors() {
+        assert_eq!(split_command("app 'title"), Err("unterminated single quote".to_owned()));
+        assert_eq!(split_command("app \"title"), Err("unterminated double quote".to_owned()));
+        assert_eq!(split_command("app \"title\\"), Err("unterminated double quote".to_owned()));
+        assert_eq!(split_command("app \\"), Err("trailing backslash".to_owned()));
+    }
+}
@@ -1,14 +1,26 @@
 use std;
-use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use futures::Future;
 use nix::unistd::Pid;
+use tokio_core::reactor::Timeout;
 use actix::prelude::*;
 
-use utils::str;
-use event::{Events, State, Reason};
-use config::ServiceConfig;
+use utils::{self, str};
+use event::{self, Events, Load, LogLine, OutputStream, ProcessStats, State, Reason};
+use config::{RestartPolicy, ServiceConfig};
+use output::LogBuffer;
+use procstat;
 use process::{self, Process, ProcessError};
-use service::FeService;
+use service::{self, FeService, Revision};
+
+/// Number of stderr lines stored in failure event
+const FAILURE_STDERR_LINES: usize = 20;
+
+/// Number of recent processes with known configuration revision
+const MAX_PROCESS_REVISIONS: usize = 4;
 
 #[allow(non_camel_case_types)]
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
@@ -37,8 +49,8 @@ pub enum WorkerMessage {
     restart,
     /// worker configuration error
     cfgerror(String),
-    /// heartbeat
-    hb,
+    /// heartbeat, optionally with worker load
+    hb(Option<Load>),
 }
 
 enum WorkerState {
@@ -48,9 +60,30 @@ enum WorkerState {
     Restarting(ProcessInfo, ProcessInfo),
     Running(ProcessInfo),
     StoppingOld(ProcessInfo, ProcessInfo),
+    /// New process is loaded and observed, old process keeps running
+    Canary(ProcessInfo, ProcessInfo),
     Stopping(ProcessInfo),
     Failed,
     Stopped,
+    /// Waiting before restart attempt
+    Backoff(Backoff),
+}
+
+struct Backoff {
+    token: usize,
+    reason: Reason,
+    /// Process that keeps running during reload, and graceful flag
+    old: Option<(ProcessInfo, bool)>,
+}
+
+/// Restart decision for exited worker process
+enum Restart {
+    /// Restart after delay
+    Delay(Duration),
+    /// Restart policy does not allow restart
+    Disabled,
+    /// Too many restarts within restart window
+    Limit,
 }
 
 struct ProcessInfo {
@@ -88,26 +121,118 @@ impl ProcessInfo {
 
 pub struct Worker {
     pub idx: usize,
-    cfg: ServiceConfig,
+    /// Configuration revision of new processes
+    rev: Rc<Revision>,
+    /// Configuration revisions of recent processes
+    revisions: VecDeque<(Pid, Rc<Revision>)>,
     state: WorkerState,
     pub events: Events,
+    pub output: LogBuffer,
     pub restore_from_fail: bool,
-    started: Instant,
-    restarts: u16,
+    /// Restart times within restart window
+    restarts: VecDeque<Instant>,
+    /// Total number of restarts
+    restart_count: u64,
+    backoff_token: usize,
+    /// Cpu time of process at last stats request
+    cpu_sample: Option<(Pid, u64, Instant)>,
+    /// Time of graceful reload of current process, `max_lifetime` with jitter
+    expires: Option<Instant>,
+    /// Keep old process until new process passes canary observation
+    canary: bool,
+    /// Reason of canary process failure
+    canary_failure: Option<Reason>,
+    /// Latest load reported by worker process
+    load: Option<(Pid, Load)>,
     addr: Addr<Unsync, FeService>,
 }
 
 impl Worker {
 
-    pub fn new(idx: usize, cfg: ServiceConfig, addr: Addr<Unsync, FeService>) -> Worker
+    pub fn new(idx: usize, rev: Rc<Revision>, addr: Addr<Unsync, FeService>) -> Worker
     {
         Worker {
-            idx, cfg, addr,
+            idx, rev, addr,
+            revisions: VecDeque::new(),
             state: WorkerState::Initial,
             events: Events::new(50),
-            started: Instant::now(),
+            output: LogBuffer::new(1000),
             restore_from_fail: false,
-            restarts: 0}
+            restarts: VecDeque::new(),
+            restart_count: 0,
+            backoff_token: 0,
+            cpu_sample: None,
+            expires: None,
+            canary: false,
+            canary_failure: None,
+            load: None}
+    }
+
+    /// Set configuration revision for new worker processes
+    pub fn set_revision(&mut self, rev: Rc<Revision>) {
+        self.rev = rev;
+    }
+
+    /// Start new process with current revision
+    fn spawn(&mut self) -> ProcessInfo {
+        let (pid, addr) = Process::start(self.idx, &self.rev.cfg, self.addr.clone());
+        if self.revisions.len() >= MAX_PROCESS_REVISIONS {
+            self.revisions.pop_front();
+        }
+        self.revisions.push_back((pid, self.rev.clone()));
+        ProcessInfo{pid, addr}
+    }
+
+    /// Revision of worker process
+    fn revision_of(&self, pid: Pid) -> Option<&Rc<Revision>> {
+        self.revisions.iter().find(|item| item.0 == pid).map(|item| &item.1)
+    }
+
+    /// Add worker event with configuration revision of the process
+    fn event(&mut self, state: State, reason: Reason, pid: Option<String>) {
+        let revision = pid.as_ref()
+            .and_then(|pid| self.revisions.iter().find(|item| item.0.to_string() == *pid))
+            .map_or(self.rev.id, |item| item.1.id);
+        self.events.add(state, reason, pid);
+        if let Some(ev) = self.events.last_mut() {
+            ev.revision = Some(revision);
+        }
+    }
+
+    /// Store line of worker output
+    pub fn add_output(&mut self, seq: u64, line: LogLine) {
+        // stderr lines could arrive after process exit
+        if line.stream == OutputStream::Stderr {
+            if let Some(ev) = self.events.last_of(&line.pid) {
+                if let Some(ref mut stderr) = ev.stderr {
+                    if stderr.len() >= FAILURE_STDERR_LINES {
+                        stderr.remove(0);
+                    }
+                    stderr.push(line.line.clone());
+                }
+            }
+        }
+        self.output.add(seq, line);
+    }
+
+    /// Attach last stderr lines of failed process to latest event
+    fn attach_stderr(&mut self, pid: Pid) {
+        let pid = format!("{}", pid);
+        let lines: Vec<&LogLine> = self.output.lines().iter()
+            .map(|item| &item.1)
+            .filter(|line| line.pid == pid && line.stream == OutputStream::Stderr)
+            .collect();
+        let skip = lines.len().saturating_sub(FAILURE_STDERR_LINES);
+        let stderr = lines.into_iter().skip(skip).map(|line| line.line.clone()).collect();
+
+        if let Some(ev) = self.events.last_mut() {
+            ev.stderr = Some(stderr);
+        }
+    }
+
+    /// Forget restart history, start requested by user or recovery
+    pub fn reset_restarts(&mut self) {
+        self.restarts.clear();
     }
 
     pub fn start(&mut self, reason: Reason) {
@@ -115,9 +240,10 @@ impl Worker {
         match self.state {
             WorkerState::Initial | WorkerState::Stopped | WorkerState::Failed => {
                 debug!("Starting worker process id: {:?}", id);
-                let (pid, addr) = Process::start(self.idx, &self.cfg, self.addr.clone());
-                self.state = WorkerState::Starting(ProcessInfo{pid, addr});
-                self.events.add(State::Starting, reason, str(pid));
+                let process = self.spawn();
+                let pid = process.pid;
+                self.state = WorkerState::Starting(process);
+                self.event(State::Starting, reason, str(pid));
             }
             _ => (),
         }
@@ -129,9 +255,9 @@ impl Worker {
         match state {
             WorkerState::Starting(p) => {
                 if p.pid == pid {
-                    self.restarts = 0;
                     p.start();
-                    self.events.add(State::Running, Reason::None, str(p.pid));
+                    self.start_lifetime();
+                    self.event(State::Running, Reason::None, str(p.pid));
                     self.state = WorkerState::Running(p);
                     self.restore_from_fail = false;
                 } else {
@@ -139,11 +265,16 @@ impl Worker {
                 }
             }
             WorkerState::Reloading(p, old) => {
-                if p.pid == pid {
-                    self.restarts = 0;
+                if p.pid == pid && self.canary {
+                    self.canary = false;
+                    p.start();
+                    self.event(State::Canary, Reason::None, str(p.pid));
+                    self.state = WorkerState::Canary(p, old);
+                } else if p.pid == pid {
                     old.stop();
                     p.start();
-                    self.events.add(State::StoppingOld, Reason::None, str(old.pid));
+                    self.start_lifetime();
+                    self.event(State::StoppingOld, Reason::None, str(old.pid));
                     self.state = WorkerState::StoppingOld(p, old);
                 } else {
                     self.state = WorkerState::Reloading(p, old);
@@ -151,10 +282,10 @@ impl Worker {
             },
             WorkerState::Restarting(p, old) => {
                 if p.pid == pid {
-                    self.restarts = 0;
                     old.quit(true);
                     p.start();
-                    self.events.add(State::StoppingOld, Reason::None, str(old.pid));
+                    self.start_lifetime();
+                    self.event(State::StoppingOld, Reason::None, str(old.pid));
                     self.state = WorkerState::StoppingOld(p, old);
                 } else {
                     self.state = WorkerState::Restarting(p, old);
@@ -179,6 +310,54 @@ impl Worker {
         }
     }
 
+    /// New process is starting, old process is still around
+    pub fn is_reloading(&self) -> bool {
+        matches!(self.state,
+                 WorkerState::Reloading(..) | WorkerState::Restarting(..) |
+                 WorkerState::StoppingOld(..) | WorkerState::Canary(..) |
+                 WorkerState::Backoff(Backoff{old: Some(..), ..}))
+    }
+
+    pub fn is_canary(&self) -> bool {
+        matches!(self.state, WorkerState::Canary(..))
+    }
+
+    pub fn canary_failure(&self) -> Option<&Reason> {
+        self.canary_failure.as_ref()
+    }
+
+    /// Check lifetime and resource usage of running process,
+    /// returns reason if process has to be recycled
+    pub fn exceeded_limits(&self) -> Option<Reason> {
+        let pid = match self.state {
+            WorkerState::Running(ref process) => process.pid,
+            _ => return None,
+        };
+        if self.expires.is_some_and(|expires| Instant::now() >= expires) {
+            info!("Worker lifetime expired (pid:{})", pid);
+            return Some(Reason::LifetimeExpired)
+        }
+        if self.rev.cfg.max_rss.is_none() && self.rev.cfg.max_cpu_seconds.is_none() {
+            return None
+        }
+        let stat = procstat::read(pid)?;
+
+        if let Some(max_rss) = self.rev.cfg.max_rss {
+            if stat.rss > max_rss {
+                info!("Worker memory limit exceeded: {} bytes (pid:{})", stat.rss, pid);
+                return Some(Reason::MemoryLimit)
+            }
+        }
+        if let Some(max_cpu) = self.rev.cfg.max_cpu_seconds {
+            let cpu = stat.cpu_ticks / procstat::clock_ticks();
+            if cpu >= max_cpu {
+                info!("Worker cpu limit exceeded: {} seconds (pid:{})", cpu, pid);
+                return Some(Reason::CpuLimit)
+            }
+        }
+        None
+    }
+
     pub fn is_stopped(&self) -> bool {
         match self.state {
             WorkerState::Stopped => true,
@@ -191,34 +370,137 @@ impl Worker {
             WorkerState::Running(ref process) => {
                 Some(process.pid)
             }
-            WorkerState::StoppingOld(ref process, _) => {
+            WorkerState::StoppingOld(ref process, _) |
+            WorkerState::Canary(ref process, _) => {
+                Some(process.pid)
+            }
+            WorkerState::Backoff(Backoff{old: Some((ref process, _)), ..}) => {
                 Some(process.pid)
             }
             _ => None
         }
     }
     
+    /// Latest load reported by current worker process
+    pub fn load(&self) -> Option<Load> {
+        match self.load {
+            Some((pid, load)) if self.pid() == Some(pid) => Some(load),
+            _ => None,
+        }
+    }
+
+    /// Resource usage of worker process
+    pub fn stats(&mut self) -> Option<ProcessStats> {
+        let pid = self.pid()?;
+        let stat = procstat::read(pid)?;
+        let now = Instant::now();
+
+        // cpu usage since last request, or average since process start
+        let ticks = procstat::clock_ticks() as f64;
+        let cpu = match self.cpu_sample {
+            Some((sample_pid, cpu_ticks, at)) if sample_pid == pid && stat.cpu_ticks >= cpu_ticks => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    (stat.cpu_ticks - cpu_ticks) as f64 / ticks / elapsed * 100.0
+                } else {
+                    0.0
+                }
+            }
+            _ if stat.uptime > 0 => stat.cpu_ticks as f64 / ticks / stat.uptime as f64 * 100.0,
+            _ => 0.0,
+        };
+        self.cpu_sample = Some((pid, stat.cpu_ticks, now));
+
+        Some(ProcessStats {
+            worker: self.idx + 1,
+            pid: pid.to_string(),
+            rss: stat.rss,
+            cpu,
+            threads: stat.threads,
+            fds: stat.fds,
+            read_bytes: stat.read_bytes,
+            write_bytes: stat.write_bytes,
+            uptime: stat.uptime,
+            restarts: self.restart_count,
+            load: self.load(),
+        })
+    }
+
+    /// Gracefully reload worker, old process keeps running until `promote()`
+    pub fn reload_canary(&mut self, reason: Reason) {
+        self.reload(true, reason);
+        self.canary = self.is_reloading();
+    }
+
+    /// Canary process passed observation, stop old process
+    pub fn promote(&mut self) {
+        let state = std::mem::replace(&mut self.state, WorkerState::Initial);
+
+        match state {
+            WorkerState::Canary(process, old_proc) => {
+                old_proc.stop();
+                self.start_lifetime();
+                self.event(State::StoppingOld, Reason::None, str(old_proc.pid));
+                self.state = WorkerState::StoppingOld(process, old_proc);
+            }
+            state => self.state = state,
+        }
+    }
+
+    /// Stop canary process and keep old process
+    pub fn rollback(&mut self, reason: Reason) {
+        let state = std::mem::replace(&mut self.state, WorkerState::Initial);
+
+        match state {
+            WorkerState::Canary(process, old_proc) => {
+                process.quit(true);
+                self.event(State::ReloadFailed, reason, str(process.pid));
+                self.restore(old_proc);
+            }
+            state => self.state = state,
+        }
+    }
+
     pub fn reload(&mut self, graceful: bool, reason: Reason) {
         let state = std::mem::replace(&mut self.state, WorkerState::Initial);
+        self.canary = false;
+        self.canary_failure = None;
 
         match state {
             WorkerState::Running(process) => {
                 // start new worker
-                let (pid, addr) = Process::start(self.idx, &self.cfg, self.addr.clone());
-                let info = ProcessInfo{pid, addr};
+                let info = self.spawn();
 
                 if graceful {
                     info!("Reloading worker: (pid:{})", process.pid);
-                    self.events.add(State::Reloading, reason, str(process.pid));
+                    self.event(State::Reloading, reason, str(process.pid));
                     self.state = WorkerState::Reloading(info, process);
                 } else {
                     info!("Restarting worker: (pid:{})", process.pid);
-                    self.events.add(State::Restarting, reason, str(process.pid));
+                    self.event(State::Restarting, reason, str(process.pid));
                     self.state = WorkerState::Restarting(info, process);
                 }
             },
+            WorkerState::Starting(process) => {
+                // process of previous revision is not loaded yet, start it over
+                if self.revision_of(process.pid).is_some_and(|rev| rev.id != self.rev.id) {
+                    info!("Restarting starting worker with new revision: (pid:{})", process.pid);
+                    process.quit(false);
+                    let process = self.spawn();
+                    let pid = process.pid;
+                    self.state = WorkerState::Starting(process);
+                    self.event(State::Starting, reason, str(pid));
+                } else {
+                    self.state = WorkerState::Starting(process);
+                }
+            },
             WorkerState::Failed | WorkerState::Stopped => {
-                self.restarts = 0;
+                self.restarts.clear();
+                self.state = WorkerState::Initial;
+                self.start(reason);
+            },
+            WorkerState::Backoff(ref backoff) if backoff.old.is_none() => {
+                self.restarts.clear();
                 self.state = WorkerState::Initial;
                 self.start(reason);
             },
@@ -232,11 +514,15 @@ impl Worker {
         match state {
             WorkerState::Initial | WorkerState::Stopped | WorkerState::Failed => {
                 self.state = WorkerState::Stopped;
-                self.events.add(State::Stopped, reason, None);
+                self.event(State::Stopped, reason, None);
+            }
+            WorkerState::Backoff(Backoff{old: None, ..}) => {
+                self.state = WorkerState::Stopped;
+                self.event(State::Stopped, reason, None);
             }
             WorkerState::Starting(process) => {
                 process.quit(true);
-                self.events.add(State::Stopping, reason, str(process.pid));
+                self.event(State::Stopping, reason, str(process.pid));
                 self.state = WorkerState::Stopping(process);
             }
             WorkerState::Stopping(process) =>
@@ -244,24 +530,30 @@ impl Worker {
             WorkerState::StoppingOld(process, old_proc) => {
                 old_proc.quit(true);
                 process.stop();
-                self.events.add(State::Stopping, reason, str(process.pid));
+                self.event(State::Stopping, reason, str(process.pid));
                 self.state = WorkerState::Stopping(process);
             }
             WorkerState::Running(process) => {
                 process.stop();
-                self.events.add(State::Stopping, reason, str(process.pid));
+                self.event(State::Stopping, reason, str(process.pid));
                 self.state = WorkerState::Stopping(process);
             }
-            WorkerState::Reloading(process, old_proc) => {
+            WorkerState::Reloading(process, old_proc) |
+            WorkerState::Canary(process, old_proc) => {
                 process.quit(true);
                 old_proc.stop();
-                self.events.add(State::Stopping, reason, str(old_proc.pid));
+                self.event(State::Stopping, reason, str(old_proc.pid));
                 self.state = WorkerState::Stopping(old_proc);
             }
             WorkerState::Restarting(process, old_proc) => {
                 process.quit(true);
                 old_proc.stop();
-                self.events.add(State::Stopping, reason, str(old_proc.pid));
+                self.event(State::Stopping, reason, str(old_proc.pid));
+                self.state = WorkerState::Stopping(old_proc);
+            }
+            WorkerState::Backoff(Backoff{old: Some((old_proc, _)), ..}) => {
+                old_proc.stop();
+                self.event(State::Stopping, reason, str(old_proc.pid));
                 self.state = WorkerState::Stopping(old_proc);
             }
         }
@@ -273,42 +565,62 @@ impl Worker {
         match state {
             WorkerState::Initial | WorkerState::Stopped | WorkerState::Failed => {
                 self.state = WorkerState::Stopped;
-                self.events.add(State::Stopped, reason, None);
+                self.event(State::Stopped, reason, None);
+            }
+            WorkerState::Backoff(Backoff{old: None, ..}) => {
+                self.state = WorkerState::Stopped;
+                self.event(State::Stopped, reason, None);
             }
             WorkerState::Starting(process) => {
                 process.quit(true);
-                self.events.add(State::Stopping, reason, str(process.pid));
+                self.event(State::Stopping, reason, str(process.pid));
+                self.state = WorkerState::Stopping(process);
+            }
+            WorkerState::Stopping(process) => {
+                // do not wait for graceful stop to finish
+                process.quit(true);
                 self.state = WorkerState::Stopping(process);
             }
-            WorkerState::Stopping(process) =>
-                self.state = WorkerState::Stopping(process),
             WorkerState::StoppingOld(process, old_proc) => {
                 old_proc.quit(true);
                 process.quit(true);
-                self.events.add(State::StoppingOld, reason, str(process.pid));
+                self.event(State::StoppingOld, reason, str(process.pid));
                 self.state = WorkerState::Stopping(process);
             }
             WorkerState::Running(process) => {
                 process.quit(true);
-                self.events.add(State::Stopping, reason, str(process.pid));
+                self.event(State::Stopping, reason, str(process.pid));
                 self.state = WorkerState::Stopping(process);
             }
-            WorkerState::Reloading(process, old_proc) => {
+            WorkerState::Reloading(process, old_proc) |
+            WorkerState::Canary(process, old_proc) => {
                 process.quit(true);
                 old_proc.quit(true);
-                self.events.add(State::Stopping, reason, str(old_proc.pid));
+                self.event(State::Stopping, reason, str(old_proc.pid));
                 self.state = WorkerState::Stopping(old_proc);
             }
             WorkerState::Restarting(process, old_proc) => {
                 process.quit(true);
                 old_proc.quit(true);
-                self.events.add(State::Stopping, reason, str(old_proc.pid));
+                self.event(State::Stopping, reason, str(old_proc.pid));
+                self.state = WorkerState::Stopping(old_proc);
+            }
+            WorkerState::Backoff(Backoff{old: Some((old_proc, _)), ..}) => {
+                old_proc.quit(true);
+                self.event(State::Stopping, reason, str(old_proc.pid));
                 self.state = WorkerState::Stopping(old_proc);
             }
         }
     }
 
     pub fn message(&mut self, pid: Pid, message: &WorkerMessage) {
+        if let WorkerMessage::hb(Some(load)) = *message {
+            if self.pid() == Some(pid) {
+                self.load = Some((pid, load));
+            }
+            return
+        }
+
         let reload = match self.state {
             WorkerState::Running(ref process) => process.pid == pid,
             _ => false
@@ -330,14 +642,14 @@ impl Worker {
     pub fn pause(&mut self, reason: Reason) {
         if let WorkerState::Running(ref process) = self.state {
             process.pause();
-            self.events.add(State::Paused, reason, str(process.pid));
+            self.event(State::Paused, reason, str(process.pid));
         }
     }
 
     pub fn resume(&mut self, reason: Reason) {
         if let WorkerState::Running(ref process) = self.state {
             process.resume();
-            self.events.add(State::Running, reason, str(process.pid));
+            self.event(State::Running, reason, str(process.pid));
         }
     }
 
@@ -352,7 +664,7 @@ impl Worker {
                     match *err {
                         ProcessError::StartupTimeout => {
                             self.state = WorkerState::Running(process);
-                            self.events.add(State::Running, err.into(), str(pid));
+                            self.event(State::Running, err.into(), str(pid));
                             self.restore_from_fail = true;
                             self.reload(false, Reason::ReloadAftreTimeout);
                             return
@@ -361,11 +673,31 @@ impl Worker {
                             // kill worker
                             process.quit(false);
 
+                            let restart = self.restart_decision(err);
+                            let failed = Worker::is_failure(err);
+                            let state = match restart {
+                                Restart::Disabled if failed => State::Failed,
+                                _ => State::Stopped,
+                            };
+                            self.event(state, err.into(), str(pid));
+                            self.attach_stderr(pid);
+
                             // start new worker
-                            self.started = Instant::now();
-                            self.state = WorkerState::Initial;
-                            self.events.add(State::Stopped, err.into(), str(pid));
-                            self.start(Reason::RestartFailedRunningWorker);
+                            match restart {
+                                Restart::Delay(delay) =>
+                                    self.backoff(delay, Reason::RestartFailedRunningWorker, None),
+                                Restart::Disabled =>
+                                    self.state = if failed {
+                                        WorkerState::Failed
+                                    } else {
+                                        WorkerState::Stopped
+                                    },
+                                Restart::Limit => {
+                                    error!("Worker restarts too often (pid:{})", pid);
+                                    self.event(State::Failed, Reason::TooManyRestarts, None);
+                                    self.state = WorkerState::Failed;
+                                }
+                            }
                         }
                     }
                 }
@@ -375,91 +707,45 @@ impl Worker {
                 if process.pid != pid {
                     self.state = WorkerState::Starting(process);
                 } else {
-                    match *err {
-                        // can not boot worker, fail immidietly
-                        //&ProcessError::InitFailed | &ProcessError::BootFailed => {
-                        //    self.state = WorkerState::Failed;
-                        //    self.events.add(State::Failed, Reason::from(err), str(pid));
-                        //    return
-                        //}
-                        ProcessError::ExitCode(0) => {
-                            // check for fast restart
-                            let now = Instant::now();
-                            if now.duration_since(self.started) > Duration::new(10, 0) {
-                                self.started = now;
-                                self.restarts = 0;
-                            } else {
-                                self.restarts += 1;
-                            }
+                    self.event(State::Failed, Reason::from(err), str(pid));
+                    self.attach_stderr(pid);
+
+                    // just in case
+                    process.quit(false);
+
+                    match self.restart_decision(err) {
+                        Restart::Delay(delay) =>
+                            self.backoff(delay, Reason::RestartFailedStartingWorker, None),
+                        Restart::Disabled => {
+                            error!("Can not start worker (pid:{})", pid);
+                            self.state = WorkerState::Failed;
+                        }
+                        Restart::Limit => {
+                            error!("Can not start worker (pid:{})", pid);
+                            self.event(State::Failed, Reason::TooManyRestarts, None);
+                            self.state = WorkerState::Failed;
                         }
-                        _ =>
-                            self.restarts += 1,
-                    }
-
-                    self.events.add(State::Failed, Reason::from(err), str(pid));
-
-                    if self.restarts < self.cfg.restarts {
-                        // just in case
-                        process.quit(false);
-
-                        // start new worker
-                        self.state = WorkerState::Initial;
-                        self.start(Reason::RestartFailedStartingWorker);
-                    } else {
-                        error!("Can not start worker (pid:{})", process.pid);
-                        self.state = WorkerState::Failed;
                     }
                 }
             }
             WorkerState::Reloading(process, old_proc) => {
                 // new process died, need to restart
                 if process.pid == pid {
-                    // can not boot worker, restore old process
-                    match *err {
-                        //&ProcessError::InitFailed | &ProcessError::BootFailed => {
-                        //    error!("Can not start worker (pid:{}), restoring old worker",
-                        //           process.pid);
-                        //    self.restore_from_fail = true;
-                        //    self.events.add(State::ReloadFailed, err.into(), str(pid));
-                        //    self.events.add(State::Running,
-                        //                    Reason::RestoreAftreFailed, str(old_proc.pid));
-                        //    self.state = WorkerState::Running(old_proc);
-                        //    return
-                        //}
-                        ProcessError::ExitCode(0) => {
-                            // check for fast restart
-                            let now = Instant::now();
-                            if now.duration_since(self.started) > Duration::new(3, 0) {
-                                self.started = now;
-                                self.restarts = 0;
-                            } else {
-                                self.restarts += 1;
-                            }
-                        }
-                        _ =>
-                            self.restarts += 1,
-                    }
+                    self.event(State::ReloadFailed, err.into(), str(pid));
+                    self.attach_stderr(pid);
 
-                    self.events.add(State::ReloadFailed, err.into(), str(pid));
-
-                    if self.restarts < self.cfg.restarts {
-                        // start new worker
-                        let (pid, addr) = Process::start(self.idx, &self.cfg, self.addr.clone());
-                        let info = ProcessInfo{pid, addr};
-                        self.state = WorkerState::Reloading(info, old_proc);
-                    } else {
-                        error!("Can not start worker (pid:{}), restoring old worker",
-                               process.pid);
-                        self.restore_from_fail = true;
-                        self.events.add(State::Running,
-                                        Reason::RestoreAftreFailed, str(old_proc.pid));
-                        self.state = WorkerState::Running(old_proc);
+                    // can not boot worker, restore old process
+                    match self.restart_decision(err) {
+                        Restart::Delay(delay) =>
+                            self.backoff(delay, Reason::RestartFailedStartingWorker,
+                                         Some((old_proc, true))),
+                        _ => self.restore(old_proc),
                     }
                 }
                 else if old_proc.pid == pid {
                     self.restore_from_fail = false;
-                    self.events.add(State::Stopped, Reason::None, str(pid));
-                    self.events.add(State::Running, Reason::None, str(process.pid));
+                    self.event(State::Stopped, Reason::None, str(pid));
+                    self.event(State::Running, Reason::None, str(process.pid));
                     self.state = WorkerState::Running(process);
                 }
                 else {
@@ -469,72 +755,68 @@ impl Worker {
             WorkerState::Restarting(process, old_proc) => {
                 // new process died, need to restart
                 if process.pid == pid {
-                    // can not boot worker, restore old process
-                    match *err {
-                        //&ProcessError::InitFailed | &ProcessError::BootFailed => {
-                        //    error!("Can not start worker (pid:{}), restoring old worker",
-                        //           process.pid);
-                        //    self.restore_from_fail = true;
-                        //    self.events.add(State::RestartFailed, err.into(), str(pid));
-                        //    self.events.add(State::Running,
-                        //                    Reason::RestoreAftreFailed, str(old_proc.pid));
-                        //    self.state = WorkerState::Running(old_proc);
-                        //    return
-                        //},
-                        ProcessError::ExitCode(0) => {
-                            // check for fast restart
-                            let now = Instant::now();
-                            if now.duration_since(self.started) > Duration::new(3, 0) {
-                                self.started = now;
-                                self.restarts = 0;
-                            } else {
-                                self.restarts += 1;
-                            }
-                        }
-                        _ => {
-                            self.restarts += 1;
-                        }
-                    }
+                    self.event(State::RestartFailed, err.into(), str(pid));
+                    self.attach_stderr(pid);
 
-                    self.events.add(State::RestartFailed, err.into(), str(pid));
-
-                    if self.restarts < self.cfg.restarts {
-                        // start new worker
-                        let (pid, addr) = Process::start(
-                            self.idx, &self.cfg, self.addr.clone());
-                        let info = ProcessInfo{pid, addr};
-                        self.state = WorkerState::Restarting(info, old_proc);
-                    } else {
-                        error!("Can not start worker (pid:{}), restoring old worker",
-                               process.pid);
-                        self.restore_from_fail = true;
-                        self.events.add(
-                            State::Running, Reason::RestoreAftreFailed, str(old_proc.pid));
-                        self.state = WorkerState::Running(old_proc);
+                    // can not boot worker, restore old process
+                    match self.restart_decision(err) {
+                        Restart::Delay(delay) =>
+                            self.backoff(delay, Reason::RestartFailedStartingWorker,
+                                         Some((old_proc, false))),
+                        _ => self.restore(old_proc),
                     }
                 }
                 else if old_proc.pid == pid {
                     self.restore_from_fail = false;
-                    self.events.add(State::Stopped, Reason::None, str(pid));
-                    self.events.add(State::Running, Reason::None, str(process.pid));
+                    self.event(State::Stopped, Reason::None, str(pid));
+                    self.event(State::Running, Reason::None, str(process.pid));
                     self.state = WorkerState::Running(process);
                 } else {
                     self.state = WorkerState::Restarting(process, old_proc);
                 }
             }
+            WorkerState::Canary(process, old_proc) => {
+                // canary process died, restore old process
+                if process.pid == pid {
+                    process.quit(false);
+                    self.event(State::ReloadFailed, err.into(), str(pid));
+                    self.attach_stderr(pid);
+                    self.canary_failure = Some(err.into());
+                    self.restore(old_proc);
+                }
+                else if old_proc.pid == pid {
+                    self.restore_from_fail = false;
+                    self.start_lifetime();
+                    self.event(State::Stopped, Reason::None, str(pid));
+                    self.event(State::Running, Reason::None, str(process.pid));
+                    self.state = WorkerState::Running(process);
+                } else {
+                    self.state = WorkerState::Canary(process, old_proc);
+                }
+            }
             WorkerState::StoppingOld(process, old_proc) => {
                 // new process died, need to restart
                 if process.pid == pid {
                     old_proc.quit(false);
-                    self.restarts += 1;
-                    self.state = WorkerState::Initial;
-                    self.events.add(State::Failed, err.into(), str(pid));
-                    self.start(Reason::NewProcessDied);
+                    self.event(State::Failed, err.into(), str(pid));
+                    self.attach_stderr(pid);
+
+                    match self.restart_decision(err) {
+                        Restart::Delay(delay) =>
+                            self.backoff(delay, Reason::NewProcessDied, None),
+                        Restart::Disabled =>
+                            self.state = WorkerState::Failed,
+                        Restart::Limit => {
+                            error!("Worker restarts too often (pid:{})", pid);
+                            self.event(State::Failed, Reason::TooManyRestarts, None);
+                            self.state = WorkerState::Failed;
+                        }
+                    }
                 }
                 else if old_proc.pid == pid {
                     self.restore_from_fail = false;
-                    self.events.add(State::Stopped, Reason::None, str(pid));
-                    self.events.add(State::Running, Reason::None, str(process.pid));
+                    self.event(State::Stopped, Reason::None, str(pid));
+                    self.event(State::Running, Reason::None, str(process.pid));
                     self.state = WorkerState::Running(process);
                 } else {
                     self.state = WorkerState::StoppingOld(process, old_proc);
@@ -543,12 +825,190 @@ impl Worker {
             WorkerState::Stopping(process) => {
                 if process.pid == pid {
                     self.state = WorkerState::Stopped;
-                    self.events.add(State::Stopped, err.into(), str(pid));
+                    self.event(State::Stopped, err.into(), str(pid));
                 } else {
                     self.state = WorkerState::Stopping(process);
                 }
             },
+            WorkerState::Backoff(mut backoff) => {
+                // old process died during backoff, start new process from scratch
+                let old_died = match backoff.old {
+                    Some((ref old_proc, _)) => old_proc.pid == pid,
+                    None => false,
+                };
+                if old_died {
+                    backoff.old = None;
+                    self.restore_from_fail = false;
+                    self.event(State::Stopped, err.into(), str(pid));
+                }
+                self.state = WorkerState::Backoff(backoff);
+            }
             state => self.state = state,
         }
     }
+
+    /// Restart delay expired, start new worker process
+    pub fn backoff_expired(&mut self, token: usize) {
+        let state = std::mem::replace(&mut self.state, WorkerState::Initial);
+
+        match state {
+            WorkerState::Backoff(backoff) => {
+                if backoff.token != token {
+                    self.state = WorkerState::Backoff(backoff);
+                    return
+                }
+                match backoff.old {
+                    Some((old_proc, graceful)) => {
+                        let info = self.spawn();
+                        self.event(State::Starting, backoff.reason, str(info.pid));
+                        self.state = if graceful {
+                            WorkerState::Reloading(info, old_proc)
+                        } else {
+                            WorkerState::Restarting(info, old_proc)
+                        };
+                    }
+                    None => self.start(backoff.reason),
+                }
+            }
+            state => self.state = state,
+        }
+    }
+
+    fn is_failure(err: &ProcessError) -> bool {
+        !matches!(*err, ProcessError::ExitCode(0))
+    }
+
+    /// Check restart policy and restart limit, compute restart delay
+    fn restart_decision(&mut self, err: &ProcessError) -> Restart {
+        restart_decision(&self.rev.cfg, &mut self.restarts, err)
+    }
+
+    /// Wait `delay` before starting new process
+    fn backoff(&mut self, delay: Duration, reason: Reason, old: Option<(ProcessInfo, bool)>) {
+        self.restart_count += 1;
+        self.backoff_token += 1;
+        let token = self.backoff_token;
+
+        let retry_at = SystemTime::now() + delay;
+        self.event(State::Backoff, reason.clone(), None);
+        if let Some(ev) = self.events.last_mut() {
+            ev.backoff = Some(event::Backoff {
+                delay: delay.as_secs() * 1000 + u64::from(delay.subsec_millis()),
+                retry_at: retry_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            });
+        }
+        self.state = WorkerState::Backoff(Backoff{token, reason, old});
+
+        let idx = self.idx;
+        let addr = self.addr.clone();
+        Arbiter::handle().spawn(
+            Timeout::new(delay, Arbiter::handle()).unwrap()
+                .then(move |_| {
+                    addr.do_send(service::BackoffExpired(idx, token));
+                    Ok(())
+                }));
+    }
+
+    /// Set expiration time of new process, jitter spreads recycling of workers
+    fn start_lifetime(&mut self) {
+        let jitter = self.rev.cfg.lifetime_jitter.unwrap_or(0) as f64 * utils::random();
+        self.expires = self.rev.cfg.max_lifetime.map(|secs| {
+            Instant::now() + Duration::new(secs, 0) + Duration::from_millis((jitter * 1000.0) as u64)
+        });
+    }
+
+    /// New process can not be started, keep old process running
+    fn restore(&mut self, old_proc: ProcessInfo) {
+        error!("Can not start worker, restoring old worker (pid:{})", old_proc.pid);
+        if let Some(rev) = self.revision_of(old_proc.pid).cloned() {
+            self.rev = rev;
+        }
+        self.canary = false;
+        self.restore_from_fail = true;
+        self.start_lifetime();
+        self.event(State::Running, Reason::RestoreAftreFailed, str(old_proc.pid));
+        self.state = WorkerState::Running(old_proc);
+    }
+}
+
+/// Check restart policy and restarts within restart window, compute restart delay
+fn restart_decision(cfg: &ServiceConfig, restarts: &mut VecDeque<Instant>, err: &ProcessError)
+                    -> Restart
+{
+    match cfg.restart {
+        RestartPolicy::Always => (),
+        RestartPolicy::OnFailure => if !Worker::is_failure(err) {
+            return Restart::Disabled
+        },
+        RestartPolicy::Never => return Restart::Disabled,
+    }
+
+    // forget restarts outside of restart window
+    let now = Instant::now();
+    let window = Duration::new(u64::from(cfg.restart_window), 0);
+    while restarts.front().is_some_and(|t| now.duration_since(*t) > window) {
+        restarts.pop_front();
+    }
+    if restarts.len() >= cfg.restarts as usize {
+        return Restart::Limit
+    }
+    restarts.push_back(now);
+
+    // exponential backoff with jitter
+    let delay = cfg.backoff_initial * cfg.backoff_multiplier.powi(restarts.len() as i32 - 1);
+    let delay = delay.min(cfg.backoff_max);
+    let jitter = delay * cfg.backoff_jitter * (utils::random() * 2.0 - 1.0);
+    let delay = (delay + jitter).max(0.0);
+    Restart::Delay(Duration::new(delay as u64, (delay.fract() * 1e9) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    fn config() -> ServiceConfig {
+        toml::from_str(r#"
+            name = "test"
+            num = 1
+            command = "app"
+            restarts = 3
+            backoff_initial = 1.0
+            backoff_multiplier = 2.0
+            backoff_max = 60.0
+            backoff_jitter = 0.0
+        "#).unwrap()
+    }
+
+    #[test]
+    fn test_restart_limit() {
+        let cfg = config();
+        let mut restarts = VecDeque::new();
+        let err = ProcessError::ExitCode(1);
+
+        let mut delays = Vec::new();
+        loop {
+            match restart_decision(&cfg, &mut restarts, &err) {
+                Restart::Delay(delay) => delays.push(delay.as_secs()),
+                Restart::Limit => break,
+                Restart::Disabled => panic!("restart is disabled"),
+            }
+            assert!(delays.len() <= 3, "restart limit is not reached");
+        }
+        assert_eq!(delays, vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let mut cfg = config();
+        let mut restarts = VecDeque::new();
+
+        cfg.restart = RestartPolicy::OnFailure;
+        assert!(matches!(restart_decision(&cfg, &mut restarts, &ProcessError::ExitCode(0)),
+                         Restart::Disabled));
+        cfg.restart = RestartPolicy::Never;
+        assert!(matches!(restart_decision(&cfg, &mut restarts, &ProcessError::ExitCode(1)),
+                         Restart::Disabled));
+        assert!(restarts.is_empty());
+    }
 }